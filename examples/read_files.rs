@@ -1,17 +1,15 @@
-use eozin::std as sreader;
 use eozin::std::Eozin;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
 fn sync_reader() {
-    let path = "../data/CMU-1.svs";
-    let path = "../data/JP2K-33003-1.svs";
+    // let path = "../data/CMU-1.svs";
+    // let path = "../data/JP2K-33003-1.svs";
     let path = "../data/CMU-1-JP2K-33005.svs";
     // let path = "../data/CMU-1-Small-Region.svs";
     // let path = "../data/Leica-1.scn";
-    // sreader::read(path);
 
-    let mut eozin = Eozin::open(path).unwrap();
+    let eozin = Eozin::open(path).unwrap();
     let lv_count = eozin.level_count;
     println!("lv_count: {:?}", lv_count);
 
@@ -22,7 +20,7 @@ fn sync_reader() {
 
     let mut output = File::create("tmp.jpeg").unwrap();
     let mut writer = BufWriter::new(&mut output);
-    writer.write(tile.buffer()).unwrap();
+    writer.write_all(tile.buffer()).unwrap();
     writer.flush().unwrap();
 }
 
@@ -0,0 +1,18 @@
+#![no_main]
+use eozin::std::Eozin;
+use libfuzzer_sys::fuzz_target;
+
+// Vendor detection, every vendor's constructor, the first tile of every level and
+// the thumbnail, which decodes tiles and associated images.
+fuzz_target!(|data: &[u8]| {
+    let Ok(e) = Eozin::open_bytes(data) else {
+        return;
+    };
+    for lv in 0..e.level_dimensions.len() {
+        let _ = e.read_tile(lv, 0, 0);
+    }
+    for name in e.associated_image_names() {
+        let _ = e.read_associated_image(&name);
+    }
+    let _ = e.get_thumbnail((32, 32));
+});
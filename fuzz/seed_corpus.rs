@@ -0,0 +1,365 @@
+//! Writes small synthetic slides into `corpus/<target>/`, seeds the fuzzers mutate
+//! into broken headers, offsets and counts.
+use std::fs;
+use std::path::Path;
+
+enum Value {
+    Ascii(&'static str),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Long8(Vec<u64>),
+    Float(f32),
+}
+
+use Value::*;
+
+type Seed = (&'static str, Vec<u8>);
+
+impl Value {
+    fn data_type(&self) -> u16 {
+        match self {
+            Ascii(_) => 2,
+            Short(_) => 3,
+            Long(_) => 4,
+            Long8(_) => 16,
+            Float(_) => 11,
+        }
+    }
+
+    fn count(&self) -> u64 {
+        match self {
+            Ascii(s) => s.len() as u64 + 1,
+            Short(v) => v.len() as u64,
+            Long(v) => v.len() as u64,
+            Long8(v) => v.len() as u64,
+            Float(_) => 1,
+        }
+    }
+}
+
+/// Tiff of either form and byte order, IFDs are chained in the order they're added.
+struct Writer {
+    big: bool,
+    moto: bool,
+    buf: Vec<u8>,
+    next_ifd_at: usize,
+}
+
+impl Writer {
+    fn new(big: bool, moto: bool) -> Self {
+        let mut w = Writer {
+            big,
+            moto,
+            buf: if moto { b"MM".to_vec() } else { b"II".to_vec() },
+            next_ifd_at: 0,
+        };
+        if big {
+            w.u16(43);
+            w.u16(8);
+            w.u16(0);
+        } else {
+            w.u16(42);
+        }
+        w.next_ifd_at = w.buf.len();
+        w.offset(0);
+        w
+    }
+
+    /// Low `n` bytes of `x` in the byte order of the file.
+    fn bytes(&self, x: u64, n: usize) -> Vec<u8> {
+        let mut b = x.to_le_bytes()[..n].to_vec();
+        if self.moto {
+            b.reverse();
+        }
+        b
+    }
+
+    fn offset_bytes(&self, x: u64) -> Vec<u8> {
+        self.bytes(x, if self.big { 8 } else { 4 })
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.buf.extend(self.bytes(x as u64, 2));
+    }
+
+    fn offset(&mut self, x: u64) {
+        self.buf.extend(self.offset_bytes(x));
+    }
+
+    /// Appends bytes such as a tile at a word boundary and returns their offset.
+    fn blob(&mut self, b: &[u8]) -> u64 {
+        if self.buf.len() % 2 == 1 {
+            self.buf.push(0);
+        }
+        let at = self.buf.len() as u64;
+        self.buf.extend_from_slice(b);
+        at
+    }
+
+    fn encode(&self, v: &Value) -> Vec<u8> {
+        match v {
+            Ascii(s) => [s.as_bytes(), &[0]].concat(),
+            Short(v) => v.iter().flat_map(|x| self.bytes(*x as u64, 2)).collect(),
+            Long(v) => v.iter().flat_map(|x| self.bytes(*x as u64, 4)).collect(),
+            Long8(v) => v.iter().flat_map(|x| self.bytes(*x, 8)).collect(),
+            Float(f) => self.bytes(f.to_bits() as u64, 4),
+        }
+    }
+
+    /// Entries of a tag given twice keep the first value, so `extra` entries of levels
+    /// override the ones made from their dimensions.
+    fn ifd(&mut self, mut entries: Vec<(u16, Value)>) {
+        entries.sort_by_key(|(tag, _)| *tag);
+        entries.dedup_by_key(|(tag, _)| *tag);
+        let inline = if self.big { 8 } else { 4 };
+        let fields: Vec<(u16, u16, u64, Vec<u8>)> = entries
+            .iter()
+            .map(|(tag, v)| {
+                let mut b = self.encode(v);
+                if b.len() > inline {
+                    let at = self.blob(&b);
+                    b = self.offset_bytes(at);
+                }
+                b.resize(inline, 0);
+                (*tag, v.data_type(), v.count(), b)
+            })
+            .collect();
+        let at = self.blob(&[]);
+        let link = self.offset_bytes(at);
+        self.buf[self.next_ifd_at..self.next_ifd_at + link.len()].copy_from_slice(&link);
+        let count = fields.len() as u64;
+        self.buf
+            .extend(self.bytes(count, if self.big { 8 } else { 2 }));
+        for (tag, dt, count, b) in fields {
+            self.u16(tag);
+            self.u16(dt);
+            self.offset(count);
+            self.buf.extend(b);
+        }
+        self.next_ifd_at = self.buf.len();
+        self.offset(0);
+    }
+
+    /// Uncompressed gray level of `tile` pixels square, every tile filled with its index.
+    fn tiled_level(&mut self, width: u32, height: u32, tile: u32, mut extra: Vec<(u16, Value)>) {
+        let tiles = width.div_ceil(tile) * height.div_ceil(tile);
+        let len = (tile * tile) as usize;
+        let offsets = (0..tiles).map(|i| self.blob(&vec![i as u8; len])).collect();
+        let offsets = if self.big {
+            Long8(offsets)
+        } else {
+            Long(offsets.into_iter().map(|o| o as u32).collect())
+        };
+        extra.extend([
+            (256, Long(vec![width])),
+            (257, Long(vec![height])),
+            (258, Short(vec![8])),
+            (259, Short(vec![1])),
+            (262, Short(vec![1])),
+            (277, Short(vec![1])),
+            (322, Long(vec![tile])),
+            (323, Long(vec![tile])),
+            (324, offsets),
+            (325, Long(vec![len as u32; tiles as usize])),
+        ]);
+        self.ifd(extra);
+    }
+
+    /// Uncompressed gray image in strips of `rows` rows.
+    fn striped_image(&mut self, width: u32, height: u32, rows: u32, mut extra: Vec<(u16, Value)>) {
+        let strips = height.div_ceil(rows);
+        let offsets = (0..strips)
+            .map(|i| {
+                let r = rows.min(height - i * rows);
+                self.blob(&vec![0x80; (width * r) as usize]) as u32
+            })
+            .collect();
+        let counts = (0..strips)
+            .map(|i| width * rows.min(height - i * rows))
+            .collect();
+        extra.extend([
+            (256, Long(vec![width])),
+            (257, Long(vec![height])),
+            (258, Short(vec![8])),
+            (259, Short(vec![1])),
+            (262, Short(vec![1])),
+            (273, Long(offsets)),
+            (277, Short(vec![1])),
+            (278, Long(vec![rows])),
+            (279, Long(counts)),
+        ]);
+        self.ifd(extra);
+    }
+}
+
+fn svs() -> Vec<u8> {
+    let mut w = Writer::new(false, false);
+    w.tiled_level(
+        40,
+        24,
+        16,
+        vec![(
+            270,
+            Ascii("Aperio Image Library v10.0.50\r\n40x24 [0,0 40x24] (16x16) RAW|AppMag = 20|MPP = 0.499"),
+        )],
+    );
+    w.striped_image(
+        10,
+        6,
+        6,
+        vec![(270, Ascii("Aperio Image Library v10.0.50\r\n10x6 -> 10x6"))],
+    );
+    w.tiled_level(
+        20,
+        12,
+        16,
+        vec![(
+            270,
+            Ascii("Aperio Image Library v10.0.50\r\n40x24 -> 20x12"),
+        )],
+    );
+    w.striped_image(
+        8,
+        8,
+        3,
+        vec![(270, Ascii("Aperio Image Library v10.0.50\r\nlabel 8x8"))],
+    );
+    w.buf
+}
+
+/// SVS whose level and thumbnail claim far more rows than they store, reading its
+/// thumbnail once allocated the claimed size.
+fn svs_huge_thumbnail() -> Vec<u8> {
+    let mut w = Writer::new(false, false);
+    w.tiled_level(
+        40,
+        24,
+        16,
+        vec![
+            (257, Long(vec![33304])),
+            (
+                270,
+                Ascii("Aperio Image Library v10.0.50\r\n40x24 [0,0 40x24] (16x16) RAW|AppMag = 20"),
+            ),
+        ],
+    );
+    w.striped_image(
+        10,
+        6,
+        6,
+        vec![
+            (257, Long(vec![1644167174])),
+            (270, Ascii("Aperio Image Library v10.0.50\r\n10x6 -> 10x6")),
+        ],
+    );
+    w.buf
+}
+
+const SCN_XML: &str = r#"<?xml version="1.0"?>
+<scn xmlns="http://www.leica-microsystems.com/scn/2010/10/01">
+  <collection name="seed" uuid="urn:uuid:0" sizeX="100000" sizeY="50000">
+    <barcode>MDAwMA==</barcode>
+    <image name="macro" uuid="urn:uuid:2">
+      <pixels sizeX="32" sizeY="16">
+        <dimension sizeX="32" sizeY="16" r="0" ifd="1" />
+      </pixels>
+      <view sizeX="100000" sizeY="50000" offsetX="0" offsetY="0" />
+    </image>
+    <image name="seed" uuid="urn:uuid:1">
+      <pixels sizeX="48" sizeY="32">
+        <dimension sizeX="24" sizeY="16" r="1" ifd="2" />
+        <dimension sizeX="48" sizeY="32" r="0" ifd="0" />
+      </pixels>
+      <view sizeX="24000" sizeY="16000" offsetX="1000" offsetY="2000" />
+      <scanSettings>
+        <objectiveSettings>
+          <objective>20</objective>
+        </objectiveSettings>
+      </scanSettings>
+    </image>
+    <image name="seed-2" uuid="urn:uuid:3">
+      <pixels sizeX="16" sizeY="32">
+        <dimension sizeX="16" sizeY="32" r="0" ifd="3" />
+      </pixels>
+      <view sizeX="8000" sizeY="16000" offsetX="30000" offsetY="2000" />
+    </image>
+  </collection>
+</scn>"#;
+
+/// Leica SCN is a BigTIFF whose first description holds the collection XML.
+fn scn() -> Vec<u8> {
+    let mut w = Writer::new(true, false);
+    w.tiled_level(48, 32, 16, vec![(270, Ascii(SCN_XML))]);
+    w.tiled_level(32, 16, 16, vec![]);
+    w.tiled_level(24, 16, 16, vec![]);
+    w.tiled_level(16, 32, 16, vec![]);
+    w.buf
+}
+
+fn bigtiff_moto() -> Vec<u8> {
+    let mut w = Writer::new(true, true);
+    w.tiled_level(32, 32, 16, vec![(282, Float(10.0))]);
+    w.striped_image(16, 16, 16, vec![]);
+    w.buf
+}
+
+fn striped_moto() -> Vec<u8> {
+    let mut w = Writer::new(false, true);
+    w.striped_image(16, 10, 4, vec![(305, Ascii("seed"))]);
+    w.buf
+}
+
+/// `eozin::fuzz::entry` input, byte order, data type, little endian count and values.
+fn entry(order: u8, dt: u8, count: u32, values: &[u8]) -> Vec<u8> {
+    let mut b = vec![order, dt];
+    b.extend(count.to_le_bytes());
+    b.extend_from_slice(values);
+    b
+}
+
+/// Abbreviated JPEG tables, i.e. SOI, a quantization and a Huffman table and EOI.
+fn jpeg_tables() -> Vec<u8> {
+    let mut b = vec![0xff, 0xd8, 0xff, 0xdb, 0x00, 0x43, 0x00];
+    b.extend([1; 64]);
+    b.extend([0xff, 0xc4, 0x00, 0x14, 0x00, 1]);
+    b.extend([0; 15]);
+    b.push(0);
+    b.extend([0xff, 0xd9]);
+    b
+}
+
+fn main() -> std::io::Result<()> {
+    let slides = [
+        ("svs", svs()),
+        ("scn", scn()),
+        ("bigtiff_moto", bigtiff_moto()),
+        ("striped_moto", striped_moto()),
+    ];
+    let entries = [
+        ("short_vec", entry(0, 3, 3, &[1, 0, 2, 0, 3, 0])),
+        ("rational", entry(1, 5, 1, &[0, 0, 0, 1, 0, 0, 0, 2])),
+        ("srational_vec", entry(0, 10, 2, &[0xff; 16])),
+        ("double", entry(1, 12, 1, &0.25f64.to_be_bytes())),
+        ("ascii", entry(0, 2, 5, b"eozin")),
+        ("slong8", entry(0, 17, 1, &(-1i64).to_le_bytes())),
+    ];
+    let tables = [("jpeg_tables", jpeg_tables())];
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    let regressions = [("svs_huge_thumbnail", svs_huge_thumbnail())];
+    let targets: [(&str, &[Seed]); 6] = [
+        ("tiff_header", &slides),
+        ("tiff_ifd_body", &slides),
+        ("open_bytes", &slides),
+        ("open_bytes", &regressions),
+        ("tiff_entry", &entries),
+        ("jpeg_tables", &tables),
+    ];
+    for (target, seeds) in targets {
+        let dir = corpus.join(target);
+        fs::create_dir_all(&dir)?;
+        for (name, b) in seeds {
+            fs::write(dir.join(name), b)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use crate::tiff::jpeg_in_tiff;
+use jpeg_decoder::{ColorTransform, Decoder, PixelFormat};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub(crate) struct DecodeError(String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Decoding image is failed: {}", self.0)
+    }
+}
+impl Error for DecodeError {}
+
+/// Owned pixel buffer, `channels` interleaved samples of 8 bits and rows `stride` bytes apart.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RgbImage {
+    pub width: u64,
+    pub height: u64,
+    pub stride: usize,
+    pub channels: u16,
+    pub data: Vec<u8>,
+}
+
+/// Bytes a JPEG or JPEG 2000 tile may decode into, a corrupt header can't claim gigabytes.
+const MAX_DECODED_LEN: usize = 1 << 28;
+
+/// APP14 transform unknown, as set by `jpeg_in_tiff::set_app14_as_unknown`, takes
+/// three samples as they are, otherwise the decoder guesses YCbCr or RGB. CMYK is
+/// converted to RGB.
+pub(crate) fn decode_jpeg(buf: &[u8]) -> Result<RgbImage, DecodeError> {
+    let mut decoder = Decoder::new(buf);
+    decoder.set_max_decoding_buffer_size(MAX_DECODED_LEN);
+    decoder
+        .read_info()
+        .map_err(|e| DecodeError(e.to_string()))?;
+    let info = decoder
+        .info()
+        .ok_or(DecodeError("JPEG without frame".to_string()))?;
+    let adobe_transform = jpeg_in_tiff::adobe_transform(buf);
+    if adobe_transform == Some(0) && info.pixel_format == PixelFormat::RGB24 {
+        decoder.set_color_transform(ColorTransform::None);
+    }
+    let mut data = decoder.decode().map_err(|e| DecodeError(e.to_string()))?;
+    let channels = match info.pixel_format {
+        PixelFormat::L8 => 1,
+        PixelFormat::RGB24 => 3,
+        PixelFormat::CMYK32 => {
+            data = cmyk_to_rgb(&data, adobe_transform.is_some());
+            3
+        }
+        PixelFormat::L16 => return Err(DecodeError("16 bit JPEG".to_string())),
+    };
+    Ok(RgbImage {
+        width: info.width as u64,
+        height: info.height as u64,
+        stride: info.width as usize * channels as usize,
+        channels,
+        data,
+    })
+}
+
+#[cfg(feature = "jp2k")]
+pub(crate) fn decode_jp2k(buf: &[u8], ycbcr: bool) -> Result<RgbImage, DecodeError> {
+    use hayro_jpeg2000::{DecodeSettings, DecoderContext, Image};
+    let image = Image::new(buf, &DecodeSettings::default())
+        .map_err(|e| DecodeError(format!("JPEG 2000 {:?}", e)))?;
+    if image.width() as u64 * image.height() as u64 * 4 > MAX_DECODED_LEN as u64 {
+        return Err(DecodeError("JPEG 2000 is too large".to_string()));
+    }
+    let mut ctx = DecoderContext::default();
+    let decoded = image
+        .decode(&mut ctx)
+        .map_err(|e| DecodeError(format!("JPEG 2000 {:?}", e)))?;
+    let channels = decoded.components().len() as u16;
+    let mut data = decoded.data_u8();
+    if ycbcr && channels == 3 {
+        ycbcr_to_rgb(&mut data);
+    }
+    Ok(RgbImage {
+        width: image.width() as u64,
+        height: image.height() as u64,
+        stride: image.width() as usize * channels as usize,
+        channels,
+        data,
+    })
+}
+
+/// CMYK samples of the decoder into RGB. The decoder inverts CMYK, so it yields the ink
+/// of Adobe's inverted samples but 255 - ink without an APP14 segment.
+fn cmyk_to_rgb(cmyk: &[u8], ink: bool) -> Vec<u8> {
+    cmyk.chunks_exact(4)
+        .flat_map(|px| {
+            let px: [u16; 4] = std::array::from_fn(|i| match ink {
+                true => 255 - px[i] as u16,
+                false => px[i] as u16,
+            });
+            [0, 1, 2].map(|i| ((px[i] * px[3] + 127) / 255) as u8)
+        })
+        .collect()
+}
+
+/// Full range YCbCr as JFIF defines it.
+#[cfg(feature = "jp2k")]
+fn ycbcr_to_rgb(data: &mut [u8]) {
+    for px in data.chunks_exact_mut(3) {
+        let (y, cb, cr) = (px[0] as f32, px[1] as f32 - 128.0, px[2] as f32 - 128.0);
+        px[0] = (y + 1.402 * cr).round().clamp(0.0, 255.0) as u8;
+        px[1] = (y - 0.344136 * cb - 0.714136 * cr)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+        px[2] = (y + 1.772 * cb).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests_decode {
+    use super::*;
+    use jpeg_encoder::{ColorType, Encoder};
+
+    fn jpeg(rgb: [u8; 3]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let pixels: Vec<u8> = rgb.iter().copied().cycle().take(16 * 16 * 3).collect();
+        Encoder::new(&mut buf, 100)
+            .encode(&pixels, 16, 16, ColorType::Rgb)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_decode_jpeg() {
+        let img = decode_jpeg(&jpeg([200, 40, 10])).unwrap();
+        assert_eq!(
+            (img.width, img.height, img.stride, img.channels),
+            (16, 16, 48, 3)
+        );
+        assert!(img.data[..3]
+            .iter()
+            .zip([200, 40, 10])
+            .all(|(a, b)| a.abs_diff(b) <= 2));
+    }
+
+    #[cfg(feature = "jp2k")]
+    #[test]
+    fn test_ycbcr_to_rgb() {
+        let mut px = [128, 128, 128, 76, 85, 255, 255, 128, 128];
+        ycbcr_to_rgb(&mut px);
+        assert_eq!(px, [128, 128, 128, 254, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_cmyk() {
+        let pixels: Vec<u8> = [0, 255, 255, 0].repeat(16 * 16);
+        for color in [ColorType::Cmyk, ColorType::CmykAsYcck] {
+            let mut buf = Vec::new();
+            Encoder::new(&mut buf, 100)
+                .encode(&pixels, 16, 16, color)
+                .unwrap();
+            let img = decode_jpeg(&buf).unwrap();
+            assert_eq!((img.channels, img.stride), (3, 48), "{:?}", color);
+            assert!(
+                img.data[..3]
+                    .iter()
+                    .zip([255, 0, 0])
+                    .all(|(a, b)| a.abs_diff(b) <= 4),
+                "{:?} is {:?}",
+                color,
+                &img.data[..3]
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_app14_unknown() {
+        let mut buf = jpeg([200, 40, 10]);
+        jpeg_in_tiff::set_app14_as_unknown(&mut buf);
+        let img = decode_jpeg(&buf).unwrap();
+        // YCbCr samples are taken as RGB as they are.
+        assert!(img.data[0].abs_diff(200) > 50);
+    }
+}
@@ -0,0 +1,85 @@
+use self::ErrorType::*;
+#[cfg(feature = "decode")]
+use crate::decode;
+use crate::tiff::ParseTiffError;
+use crate::vendor::leica::ParseLeicaError;
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub struct EozinError {
+    t: ErrorType,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
+enum ErrorType {
+    IoError(io::Error),
+    TiffError(ParseTiffError),
+    ParseWsiError(String),
+    MiscError(String),
+}
+impl fmt::Display for EozinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.t {
+            IoError(e) => write!(f, "IO Error {}", e).unwrap(),
+            TiffError(e) => write!(f, "Parse Tiff Error {}", e).unwrap(),
+            ParseWsiError(e) => write!(
+                f,
+                "Couldn't interpret given tiff file as Whole Slide Image {}",
+                e
+            )
+            .unwrap(),
+            MiscError(e) => write!(f, "Error {}", e).unwrap(),
+        }
+        Ok(())
+    }
+}
+
+impl EozinError {
+    pub fn new(msg: &str) -> Self {
+        EozinError {
+            t: MiscError(msg.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for EozinError {
+    fn from(err: io::Error) -> EozinError {
+        EozinError { t: IoError(err) }
+    }
+}
+impl From<ParseTiffError> for EozinError {
+    fn from(err: ParseTiffError) -> EozinError {
+        EozinError { t: TiffError(err) }
+    }
+}
+#[cfg(feature = "decode")]
+impl From<decode::DecodeError> for EozinError {
+    fn from(err: decode::DecodeError) -> EozinError {
+        EozinError {
+            t: MiscError(err.to_string()),
+        }
+    }
+}
+impl From<ParseLeicaError> for EozinError {
+    fn from(err: ParseLeicaError) -> EozinError {
+        EozinError {
+            t: ParseWsiError(err.to_string()),
+        }
+    }
+}
+#[cfg(feature = "wasm")]
+impl From<wasm_bindgen::JsValue> for EozinError {
+    fn from(err: wasm_bindgen::JsValue) -> EozinError {
+        EozinError {
+            t: MiscError(format!("js error {:?}", err)),
+        }
+    }
+}
+impl error::Error for EozinError {}
+
+pub(crate) fn missing(s: &str) -> EozinError {
+    EozinError {
+        t: ParseWsiError(s.to_string()),
+    }
+}
@@ -0,0 +1,58 @@
+//! Entry points of the targets under `fuzz/`, crate internals fed with untrusted bytes.
+//! Not a stable API, every function only has to return without panicking.
+use crate::tiff::data::DataType;
+use crate::tiff::jpeg_in_tiff;
+use crate::tiff::parser::{Bytes, Parser};
+use std::collections::HashMap;
+
+pub fn header(data: &[u8]) {
+    let _ = Parser::header(data);
+}
+
+/// First IFD of a whole tiff, sliced without the bounds `read_tiff` checks first,
+/// and the same entries read as the ones of an NDPI beyond 4GB.
+pub fn ifd_body(data: &[u8]) {
+    let Ok((p, ofs)) = Parser::header(data) else {
+        return;
+    };
+    let Some(i) = usize::try_from(ofs).ok().and_then(|o| data.get(o..)) else {
+        return;
+    };
+    let Ok(count) = p.ifd_count(i) else {
+        return;
+    };
+    let size = p.size();
+    let body = i.get(size.ifd_header as usize..).unwrap_or_default();
+    let body = body.get(..size.ifd_body(count) as usize).unwrap_or(body);
+    let _ = p.ifd_body(body, &mut HashMap::new(), &mut Vec::new());
+    if let Parser::Classic(_) = p {
+        let (entries, ext) = body.split_at(body.len().min(12 * count as usize));
+        let _ = p.ndpi_ifd_body(entries, ext, &mut HashMap::new(), &mut Vec::new());
+    }
+}
+
+/// Byte order, data type and count from the first 6 bytes, values from the rest.
+pub fn entry(data: &[u8]) {
+    let [order, dt, c @ ..] = data else {
+        return;
+    };
+    let Some((count, values)) = c.split_first_chunk::<4>() else {
+        return;
+    };
+    let Some(dt) = DataType::from_u16(*dt as u16) else {
+        return;
+    };
+    let bytes = match order & 1 {
+        0 => Bytes::Intel,
+        _ => Bytes::Moto,
+    };
+    let _ = Parser::Classic(bytes).entry(u32::from_le_bytes(*count) as u64, dt, values);
+}
+
+/// JPEGTables of a tiled IFD, patched as the levels of RGB images are.
+pub fn jpeg_tables(data: &[u8]) {
+    let mut tables = data.to_vec();
+    jpeg_in_tiff::set_app14_as_unknown(&mut tables);
+    let _ = jpeg_in_tiff::adobe_transform(&tables);
+    let _ = jpeg_in_tiff::join_strips(Some(&tables), &[data.to_vec(), data.to_vec()], 16);
+}
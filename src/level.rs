@@ -0,0 +1,381 @@
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
+#[cfg(feature = "decode")]
+use crate::decode::{self, RgbImage};
+use crate::error::{missing, EozinError};
+use crate::source::{read_bytes, with_bytes, AsyncByteSource};
+use crate::tiff::{
+    compression, jpeg_in_tiff,
+    property::{self, expect_short, first_short, u8vec},
+    tag::*,
+    IFD,
+};
+
+#[non_exhaustive]
+#[derive(Clone)]
+pub enum Tile {
+    Jpeg(Vec<u8>),
+    /// JPEG 2000 codestream, Aperio 33003 stores YCbCr without saying so in the codestream.
+    Jp2k {
+        ycbcr: bool,
+        data: Vec<u8>,
+    },
+    /// Uncompressed chunky samples, row by row.
+    Raw {
+        width: u64,
+        height: u64,
+        samples_per_pixel: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl Tile {
+    pub fn buffer(&self) -> &Vec<u8> {
+        match self {
+            Tile::Jpeg(v) => v,
+            Tile::Jp2k { data, .. } => data,
+            Tile::Raw { data, .. } => data,
+        }
+    }
+
+    /// Decodes JPEG into RGB or grayscale, raw tiles keep their samples.
+    #[cfg(feature = "decode")]
+    pub fn decode(&self) -> Result<RgbImage, EozinError> {
+        match self {
+            Tile::Jpeg(buf) => Ok(decode::decode_jpeg(buf)?),
+            #[cfg(feature = "jp2k")]
+            Tile::Jp2k { ycbcr, data } => Ok(decode::decode_jp2k(data, *ycbcr)?),
+            #[cfg(not(feature = "jp2k"))]
+            Tile::Jp2k { .. } => Err(EozinError::new("Enable jp2k feature to decode JPEG 2000")),
+            Tile::Raw {
+                width,
+                height,
+                samples_per_pixel,
+                data,
+            } if raw_len(*width, *height, *samples_per_pixel, 8)? == data.len() => Ok(RgbImage {
+                width: *width,
+                height: *height,
+                stride: *width as usize * *samples_per_pixel as usize,
+                channels: *samples_per_pixel,
+                data: data.clone(),
+            }),
+            Tile::Raw { .. } => Err(raw_mismatch()),
+        }
+    }
+}
+
+pub(crate) struct TiledLevel {
+    compression: u16,
+    jpeg_tables: Option<Vec<u8>>,
+    predictor: u16,
+    samples_per_pixel: u16,
+    bits_per_sample: u16,
+    pub t: property::TiledIfd,
+}
+
+/// Non-tiled image such as a label or a macro, every strip is a virtual tile
+/// of `rows_per_strip` rows, or the image is read as a single tile.
+pub(crate) struct StripedImage {
+    compression: u16,
+    jpeg_tables: Option<Vec<u8>>,
+    predictor: u16,
+    samples_per_pixel: u16,
+    bits_per_sample: u16,
+    pub t: property::StripedIfd,
+}
+
+/// Pyramid level of a generic tiff.
+pub(crate) enum Level {
+    Tiled(TiledLevel),
+    Striped(StripedImage),
+}
+
+impl TiledLevel {
+    pub(crate) fn new(ifd: &IFD) -> Option<Self> {
+        let compression = ifd.get(&Compression).and_then(expect_short)?;
+        let t = property::tiled_ifd(ifd)?;
+        Some(TiledLevel {
+            compression,
+            jpeg_tables: jpeg_tables(ifd),
+            predictor: ifd.get(&Predictor).and_then(expect_short).unwrap_or(1),
+            samples_per_pixel: ifd
+                .get(&SamplesPerPixel)
+                .and_then(expect_short)
+                .unwrap_or(1),
+            bits_per_sample: ifd.get(&BitPerSample).and_then(first_short).unwrap_or(1),
+            t,
+        })
+    }
+
+    pub(crate) async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        let num_tiles_across = self.t.width.div_ceil(self.t.tile_width);
+        let num_tiles_down = self.t.height.div_ceil(self.t.tile_height);
+        if x as u64 >= num_tiles_across || y as u64 >= num_tiles_down {
+            return Err(missing("selected tile is out of index"));
+        }
+        let tile_id = (num_tiles_across as usize)
+            .checked_mul(y)
+            .and_then(|i| i.checked_add(x))
+            .ok_or(missing("selected tile is out of index"))?;
+        let addr = self.t.offsets.get(source, tile_id).await?;
+        let len = self.t.byte_counts.get(source, tile_id).await?;
+        let (addr, len) = addr
+            .zip(len)
+            .ok_or(missing("selected tile is out of index"))?;
+        with_bytes(source, addr, addr.saturating_add(len), |buf| self.tile(buf)).await?
+    }
+
+    fn tile(&self, buf: &[u8]) -> Result<Tile, EozinError> {
+        match (&self.jpeg_tables, self.compression) {
+            (Some(j_tb), compression::JPEG) => {
+                Ok(Tile::Jpeg(jpeg_in_tiff::with_tables(Some(j_tb), buf)))
+            }
+            (None, compression::JPEG) => Ok(Tile::Jpeg(buf.to_vec())),
+            (_, compression::APERIO_JP2K_YCBCR) => Ok(Tile::Jp2k {
+                ycbcr: true,
+                data: buf.to_vec(),
+            }),
+            (_, compression::APERIO_JP2K_RGB) => Ok(Tile::Jp2k {
+                ycbcr: false,
+                data: buf.to_vec(),
+            }),
+            (_, c) => {
+                let len = raw_len(
+                    self.t.tile_width,
+                    self.t.tile_height,
+                    self.samples_per_pixel,
+                    self.bits_per_sample,
+                )?;
+                let mut data = decompress(c, buf, len)?;
+                if data.len() != len {
+                    return Err(raw_mismatch());
+                }
+                undo_predictor(
+                    self.predictor,
+                    self.bits_per_sample,
+                    &mut data,
+                    self.t.tile_width,
+                    self.samples_per_pixel,
+                )?;
+                Ok(Tile::Raw {
+                    width: self.t.tile_width,
+                    height: self.t.tile_height,
+                    samples_per_pixel: self.samples_per_pixel,
+                    data,
+                })
+            }
+        }
+    }
+}
+
+impl StripedImage {
+    pub(crate) fn new(ifd: &IFD) -> Option<Self> {
+        Some(StripedImage {
+            compression: ifd.get(&Compression).and_then(expect_short)?,
+            jpeg_tables: jpeg_tables(ifd),
+            predictor: ifd.get(&Predictor).and_then(expect_short).unwrap_or(1),
+            samples_per_pixel: ifd
+                .get(&SamplesPerPixel)
+                .and_then(expect_short)
+                .unwrap_or(1),
+            bits_per_sample: ifd.get(&BitPerSample).and_then(first_short).unwrap_or(1),
+            t: property::striped_ifd(ifd)?,
+        })
+    }
+
+    /// Strip `y` as a tile of the image width, `x` is always 0.
+    pub(crate) async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        let (addr, len) = match x {
+            0 => self
+                .t
+                .offsets
+                .get(y)
+                .and_then(|a| self.t.byte_counts.get(y).map(|l| (*a, *l))),
+            _ => None,
+        }
+        .ok_or(missing("selected tile is out of index"))?;
+        with_bytes(source, addr, addr.saturating_add(len), |buf| {
+            match self.compression {
+                compression::JPEG => Ok(Tile::Jpeg(jpeg_in_tiff::with_tables(
+                    self.jpeg_tables.as_deref(),
+                    buf,
+                ))),
+                _ => self.decode_raw(&[buf], self.t.rows(y as u64)),
+            }
+        })
+        .await?
+    }
+
+    /// Whole image, JPEG strips are joined into a single JPEG.
+    pub(crate) async fn read<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+    ) -> Result<Tile, EozinError> {
+        let mut strips = Vec::with_capacity(self.t.offsets.len());
+        for (addr, len) in self.t.offsets.iter().zip(self.t.byte_counts.iter()) {
+            strips.push(read_bytes(source, *addr, addr.saturating_add(*len)).await?);
+        }
+        match self.compression {
+            compression::JPEG => {
+                jpeg_in_tiff::join_strips(self.jpeg_tables.as_deref(), &strips, self.t.height)
+                    .map(Tile::Jpeg)
+                    .ok_or(EozinError::new("Couldn't join JPEG strips"))
+            }
+            _ => self.decode_raw(&strips, self.t.height),
+        }
+    }
+
+    fn decode_raw<B: AsRef<[u8]>>(&self, strips: &[B], height: u64) -> Result<Tile, EozinError> {
+        let len = raw_len(
+            self.t.width,
+            height,
+            self.samples_per_pixel,
+            self.bits_per_sample,
+        )?;
+        let mut data = Vec::new();
+        for strip in strips.iter() {
+            let mut buf = decompress(self.compression, strip.as_ref(), len - data.len())?;
+            undo_predictor(
+                self.predictor,
+                self.bits_per_sample,
+                &mut buf,
+                self.t.width,
+                self.samples_per_pixel,
+            )?;
+            data.extend_from_slice(&buf);
+        }
+        if data.len() != len {
+            return Err(raw_mismatch());
+        }
+        Ok(Tile::Raw {
+            width: self.t.width,
+            height,
+            samples_per_pixel: self.samples_per_pixel,
+            data,
+        })
+    }
+}
+
+impl Level {
+    pub(crate) fn dimensions(&self) -> (u64, u64) {
+        match self {
+            Level::Tiled(lv) => (lv.t.width, lv.t.height),
+            Level::Striped(lv) => (lv.t.width, lv.t.height),
+        }
+    }
+
+    pub(crate) fn tile_size(&self) -> (u64, u64) {
+        match self {
+            Level::Tiled(lv) => (lv.t.tile_width, lv.t.tile_height),
+            Level::Striped(lv) => (lv.t.width, lv.t.rows_per_strip),
+        }
+    }
+
+    pub(crate) async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        match self {
+            Level::Tiled(lv) => lv.read_tile(source, x, y).await,
+            Level::Striped(lv) => lv.read_tile(source, x, y).await,
+        }
+    }
+}
+
+/// Tile `(x, y)` of the `lv`th of `levels`, vendors whose levels are IFDs as they are
+/// read their tiles by it.
+pub(crate) async fn read_tile<S: AsyncByteSource + ?Sized>(
+    levels: &[Level],
+    source: &S,
+    lv: usize,
+    x: usize,
+    y: usize,
+) -> Result<Tile, EozinError> {
+    let lv = levels.get(lv).ok_or(missing("level"))?;
+    lv.read_tile(source, x, y).await
+}
+
+/// Raw tiles and strip images beyond this many bytes are refused before anything is
+/// inflated or allocated, whatever their IFD claims.
+const MAX_RAW_LEN: u64 = 1 << 28;
+
+/// Bytes of `width` x `height` pixels of whole bytes samples.
+fn raw_len(
+    width: u64,
+    height: u64,
+    samples_per_pixel: u16,
+    bits_per_sample: u16,
+) -> Result<usize, EozinError> {
+    width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(samples_per_pixel as u64))
+        .and_then(|n| n.checked_mul(bits_per_sample.div_ceil(8) as u64))
+        .filter(|n| *n <= MAX_RAW_LEN)
+        .map(|n| n as usize)
+        .ok_or(EozinError::new("Raw image is too large"))
+}
+
+fn raw_mismatch() -> EozinError {
+    EozinError::new("Raw samples don't match the image dimensions")
+}
+
+/// Lossless compressions, the codecs are built with the native feature only.
+/// Output beyond `limit` bytes fails.
+fn decompress(c: u16, buf: &[u8], limit: usize) -> Result<Vec<u8>, EozinError> {
+    match c {
+        #[cfg(feature = "native")]
+        compression::NONE | compression::LZW | compression::DEFLATE | compression::ZSTD => {
+            Ok(compression::decompress(c, buf, limit)?)
+        }
+        #[cfg(not(feature = "native"))]
+        compression::NONE if buf.len() <= limit => Ok(buf.to_vec()),
+        #[cfg(not(feature = "native"))]
+        compression::NONE => Err(raw_mismatch()),
+        _ => Err(EozinError::new("Unknown compression")),
+    }
+}
+
+const PHOTOMETRIC_YCBCR: u16 = 6;
+
+/// JPEGTables of RGB images are marked as not color transformed, so decoders
+/// don't convert them from YCbCr.
+fn jpeg_tables(ifd: &IFD) -> Option<Vec<u8>> {
+    let mut jptb = ifd.get(&JPEGTables).and_then(u8vec)?.clone();
+    let photometric = ifd.get(&PhotometricInterpretation).and_then(expect_short);
+    if photometric != Some(PHOTOMETRIC_YCBCR) {
+        jpeg_in_tiff::set_app14_as_unknown(&mut jptb);
+    }
+    Some(jptb)
+}
+
+fn undo_predictor(
+    predictor: u16,
+    bits_per_sample: u16,
+    data: &mut [u8],
+    width: u64,
+    samples_per_pixel: u16,
+) -> Result<(), EozinError> {
+    match (predictor, bits_per_sample) {
+        (1, _) => Ok(()),
+        (2, 8) => {
+            compression::undo_horizontal_predictor(
+                data,
+                width as usize,
+                samples_per_pixel as usize,
+            );
+            Ok(())
+        }
+        _ => Err(EozinError::new("Unsupported predictor")),
+    }
+}
@@ -1,7 +1,17 @@
+#[cfg(feature = "decode")]
+pub(crate) mod decode;
+pub(crate) mod error;
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz;
+pub(crate) mod level;
+#[cfg(feature = "decode")]
+pub(crate) mod region;
+pub(crate) mod slide;
+pub mod source;
 #[cfg(feature = "native")]
 pub mod std;
-pub(crate) mod tiff;
+pub mod tiff;
 pub(crate) mod vendor;
 #[cfg(feature = "wasm")]
 pub mod wasm;
-
@@ -0,0 +1,321 @@
+use crate::decode::RgbImage;
+use crate::error::EozinError;
+
+/// RGBA bytes of the largest region read at once, 16384 x 16384 pixels.
+const MAX_CANVAS_LEN: u64 = 1 << 30;
+
+/// Rectangle of a level in its own pixels, `x` and `y` may be negative or beyond the level.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Region {
+    pub x: i64,
+    pub y: i64,
+    pub width: u64,
+    pub height: u64,
+}
+
+impl Region {
+    /// Region of a level whose `downsample` is relative to level 0, as OpenSlide's `read_region`.
+    /// Sizes beyond `i64::MAX` are refused.
+    pub(crate) fn new(
+        location: (i64, i64),
+        downsample: f64,
+        size: (u64, u64),
+    ) -> Result<Self, EozinError> {
+        if size.0 > i64::MAX as u64 || size.1 > i64::MAX as u64 {
+            return Err(EozinError::new(&format!(
+                "Region of {}x{} pixels is too large",
+                size.0, size.1
+            )));
+        }
+        Ok(Region {
+            x: (location.0 as f64 / downsample).floor() as i64,
+            y: (location.1 as f64 / downsample).floor() as i64,
+            width: size.0,
+            height: size.1,
+        })
+    }
+
+    /// Overlap with a level of `dimensions` as `(x0, y0, x1, y1)`, edges are summed in
+    /// i128 so no location and size overflow.
+    fn clip(&self, dimensions: (u64, u64)) -> Option<(u64, u64, u64, u64)> {
+        let x0 = self.x.max(0) as u64;
+        let y0 = self.y.max(0) as u64;
+        let x1 = (self.x as i128 + self.width as i128).min(dimensions.0 as i128);
+        let y1 = (self.y as i128 + self.height as i128).min(dimensions.1 as i128);
+        if x1 <= x0 as i128 || y1 <= y0 as i128 {
+            return None;
+        }
+        Some((x0, y0, x1 as u64, y1 as u64))
+    }
+
+    /// Tiles overlapping the region as `(x, y)` indices, row by row.
+    pub(crate) fn tiles(&self, dimensions: (u64, u64), tile_size: (u64, u64)) -> Vec<(u64, u64)> {
+        let (tw, th) = tile_size;
+        match self.clip(dimensions) {
+            Some((x0, y0, x1, y1)) if tw > 0 && th > 0 => (y0 / th..=(y1 - 1) / th)
+                .flat_map(|j| (x0 / tw..=(x1 - 1) / tw).map(move |i| (i, j)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Empty RGBA buffer of the region, every pixel is transparent. Regions beyond
+    /// [`MAX_CANVAS_LEN`] bytes are refused before allocating.
+    pub(crate) fn canvas(&self) -> Result<RgbImage, EozinError> {
+        let len = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|n| n.checked_mul(4))
+            .filter(|n| *n <= MAX_CANVAS_LEN)
+            .ok_or(EozinError::new(&format!(
+                "Region of {}x{} pixels is too large",
+                self.width, self.height
+            )))?;
+        Ok(RgbImage {
+            width: self.width,
+            height: self.height,
+            stride: self.width as usize * 4,
+            channels: 4,
+            data: vec![0; len as usize],
+        })
+    }
+
+    /// Copies the pixels of a tile at `origin` of the level into `canvas` as RGBA,
+    /// pixels beyond the level, e.g. padding of edge tiles, are left transparent.
+    pub(crate) fn paint(
+        &self,
+        canvas: &mut RgbImage,
+        dimensions: (u64, u64),
+        origin: (u64, u64),
+        tile: &RgbImage,
+    ) {
+        let (x0, y0, x1, y1) = match self.clip(dimensions) {
+            Some(c) => c,
+            None => return,
+        };
+        let x0 = x0.max(origin.0);
+        let y0 = y0.max(origin.1);
+        let x1 = x1.min(origin.0 + tile.width);
+        let y1 = y1.min(origin.1 + tile.height);
+        let c = tile.channels as usize;
+        for y in y0..y1 {
+            let src = tile.stride * (y - origin.1) as usize;
+            let dst = canvas.stride * (y as i128 - self.y as i128) as usize;
+            for x in x0..x1 {
+                let s = src + c * (x - origin.0) as usize;
+                let d = dst + 4 * (x as i128 - self.x as i128) as usize;
+                let (px, rgba) = match (tile.data.get(s..s + c), canvas.data.get_mut(d..d + 4)) {
+                    (Some(px), Some(rgba)) => (px, rgba),
+                    _ => continue,
+                };
+                match c {
+                    1 => rgba.copy_from_slice(&[px[0], px[0], px[0], 255]),
+                    2 => rgba.copy_from_slice(&[px[0], px[0], px[0], px[1]]),
+                    3 => rgba.copy_from_slice(&[px[0], px[1], px[2], 255]),
+                    4.. => rgba.copy_from_slice(&px[..4]),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Resizes with an area filter, every output pixel averages the source pixels it covers.
+pub(crate) fn resize_area(img: &RgbImage, width: u64, height: u64) -> Result<RgbImage, EozinError> {
+    let mut r = AreaResize::new((img.width, img.height), (width, height), img.channels)?;
+    r.add((0, 0), img);
+    Ok(r.finish())
+}
+
+/// Area resize of a source added piece by piece, e.g. tile by tile, so only the output
+/// is held. Four channels are RGBA whose alpha weights the colours of a pixel.
+pub(crate) struct AreaResize {
+    src: (u64, u64),
+    width: u64,
+    height: u64,
+    channels: usize,
+    acc: Vec<f32>,
+}
+
+impl AreaResize {
+    /// Output of `dst` pixels for a source of `src`, outputs of more than [`MAX_CANVAS_LEN`]
+    /// bytes as RGBA are refused.
+    pub(crate) fn new(src: (u64, u64), dst: (u64, u64), channels: u16) -> Result<Self, EozinError> {
+        let pixels = dst
+            .0
+            .checked_mul(dst.1)
+            .filter(|n| n.checked_mul(4).is_some_and(|len| len <= MAX_CANVAS_LEN))
+            .ok_or(EozinError::new(&format!(
+                "Image of {}x{} pixels is too large",
+                dst.0, dst.1
+            )))?;
+        Ok(AreaResize {
+            src,
+            width: dst.0,
+            height: dst.1,
+            channels: channels as usize,
+            acc: vec![0.0; pixels as usize * channels as usize],
+        })
+    }
+
+    /// Adds the pixels of `img` at `origin` of the source, its channels are the ones of
+    /// the resize and pixels beyond the source are ignored.
+    pub(crate) fn add(&mut self, origin: (u64, u64), img: &RgbImage) {
+        let c = self.channels;
+        let row = self.width as usize * c;
+        let xs: Vec<_> = (0..img.width)
+            .map(|x| covered(origin.0 + x, self.src.0, self.width))
+            .collect();
+        let mut px = vec![0f32; c];
+        for y in 0..img.height {
+            let ys = covered(origin.1 + y, self.src.1, self.height);
+            for (x, xs) in xs.iter().enumerate() {
+                let s = img.stride * y as usize + c * x;
+                let Some(src) = img.data.get(s..s + c) else {
+                    continue;
+                };
+                // colours are premultiplied, so transparent pixels don't darken their neighbours
+                let alpha = if c == 4 { src[3] as f32 / 255.0 } else { 1.0 };
+                for (k, p) in px.iter_mut().enumerate() {
+                    *p = match k {
+                        3 => src[k] as f32,
+                        _ => src[k] as f32 * alpha,
+                    };
+                }
+                for &(dy, wy) in &ys {
+                    for &(dx, wx) in xs {
+                        let d = row * dy + c * dx;
+                        for (a, p) in self.acc[d..d + c].iter_mut().zip(&px) {
+                            *a += wy * wx * p;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> RgbImage {
+        let c = self.channels;
+        let data = self
+            .acc
+            .chunks_exact(c)
+            .flat_map(|px| {
+                let alpha = if c == 4 { px[3] / 255.0 } else { 1.0 };
+                px.iter().enumerate().map(move |(k, v)| match k {
+                    3 => *v,
+                    _ if alpha > 0.0 => v / alpha,
+                    _ => 0.0,
+                })
+            })
+            .map(|v| v.round().clamp(0.0, 255.0) as u8)
+            .collect();
+        RgbImage {
+            width: self.width,
+            height: self.height,
+            stride: self.width as usize * c,
+            channels: c as u16,
+            data,
+        }
+    }
+}
+
+/// Output pixels which source pixel `s` covers and its share of each, the shares of an
+/// output pixel sum to 1. Nothing beyond the source.
+fn covered(s: u64, src: u64, dst: u64) -> Vec<(usize, f32)> {
+    if s >= src || dst == 0 {
+        return Vec::new();
+    }
+    let scale = src as f64 / dst as f64;
+    let (start, end) = (s as f64, s as f64 + 1.0);
+    let first = (start / scale).floor() as u64;
+    let last = ((end / scale).ceil() as u64).min(dst);
+    (first..last)
+        .filter_map(|d| {
+            let cover = end.min((d + 1) as f64 * scale) - start.max(d as f64 * scale);
+            (cover > 0.0).then_some((d as usize, (cover / scale) as f32))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests_region {
+    use super::*;
+
+    #[test]
+    fn test_tiles() {
+        let r = Region::new((-20, 400), 2.0, (300, 10)).unwrap();
+        assert_eq!((r.x, r.y), (-10, 200));
+        assert_eq!(r.tiles((1000, 1000), (256, 256)), vec![(0, 0), (1, 0)]);
+        assert_eq!(r.tiles((200, 1000), (256, 256)), vec![(0, 0)]);
+        assert_eq!(r.tiles((1000, 200), (256, 256)), vec![]);
+        assert!(Region::new((0, 0), 1.0, (40, 1 << 30))
+            .unwrap()
+            .canvas()
+            .is_err());
+        let r = Region::new((i64::MAX, 0), 1.0, (i64::MAX as u64, 0)).unwrap();
+        assert_eq!(r.tiles((1000, 1000), (256, 256)), vec![]);
+        let r = Region::new((i64::MAX - 10, 0), 1.0, (1000, 10)).unwrap();
+        assert!(!r.tiles((u64::MAX, 1000), (256, 256)).is_empty());
+        assert!(Region::new((0, 0), 1.0, (u64::MAX, 0)).is_err());
+    }
+
+    #[test]
+    fn test_resize_area() {
+        let img = RgbImage {
+            width: 3,
+            height: 2,
+            stride: 3,
+            channels: 1,
+            data: vec![0, 30, 90, 60, 90, 150],
+        };
+        let small = resize_area(&img, 2, 1).unwrap();
+        assert_eq!((small.width, small.height, small.stride), (2, 1, 2));
+        assert_eq!(small.data, vec![40, 100]);
+
+        // pieces of the image give the same output as the whole
+        let mut r = AreaResize::new((3, 2), (2, 1), 1).unwrap();
+        for (x, px) in [(0, vec![0, 60]), (1, vec![30, 90]), (2, vec![90, 150])] {
+            let column = RgbImage {
+                width: 1,
+                height: 2,
+                stride: 1,
+                channels: 1,
+                data: px,
+            };
+            r.add((x, 0), &column);
+        }
+        assert_eq!(r.finish().data, vec![40, 100]);
+        assert!(AreaResize::new((3, 2), (1 << 15, 1 << 15), 4).is_err());
+    }
+
+    #[test]
+    fn test_resize_premultiplied() {
+        let img = RgbImage {
+            width: 2,
+            height: 1,
+            stride: 8,
+            channels: 4,
+            data: vec![200, 100, 50, 255, 0, 0, 0, 0],
+        };
+        let small = resize_area(&img, 1, 1).unwrap();
+        assert_eq!(small.data, vec![200, 100, 50, 128]);
+    }
+
+    #[test]
+    fn test_paint() {
+        let r = Region::new((-1, 0), 1.0, (3, 2)).unwrap();
+        let mut canvas = r.canvas().unwrap();
+        let tile = RgbImage {
+            width: 2,
+            height: 2,
+            stride: 6,
+            channels: 3,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        };
+        r.paint(&mut canvas, (1, 2), (0, 0), &tile);
+        assert_eq!(
+            canvas.data,
+            vec![0, 0, 0, 0, 1, 2, 3, 255, 0, 0, 0, 0, 0, 0, 0, 0, 7, 8, 9, 255, 0, 0, 0, 0]
+        );
+    }
+}
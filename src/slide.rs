@@ -0,0 +1,933 @@
+//! Slides and their vendors read through any [`AsyncByteSource`], shared by the sync
+//! reader of `std` and the browser one of `wasm`.
+mod cache;
+mod registry;
+
+use self::cache::TileCache;
+pub use self::cache::{CacheStats, CachedTiles};
+pub use self::registry::{Confidence, Registry, Slide, Vendor};
+#[cfg(feature = "decode")]
+pub use crate::decode::RgbImage;
+use crate::error::missing;
+pub use crate::error::EozinError;
+pub use crate::level::Tile;
+use crate::level::{self, Level, StripedImage, TiledLevel};
+#[cfg(feature = "decode")]
+use crate::region::{self, Region};
+use crate::source;
+pub use crate::source::{AsyncByteSource, BoxFuture, ByteSource, SlideSource};
+use crate::tiff::{property, reader, tag, Tiff};
+pub use crate::vendor::aperio::AperioMetadata;
+use crate::vendor::{aperio, leica, ndpi, openslide, philips, ventana};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Slide read through a source `S`, files, buffers and other [`ByteSource`]s are boxed.
+/// Every read has an `_async` counterpart, which reads an [`AsyncByteSource`] without blocking.
+pub struct Eozin<S = Box<dyn ByteSource>> {
+    slide: Box<dyn Slide>,
+    source: S,
+    size: u64,
+    cache: Option<TileCache>,
+    pub vendor: String,
+    pub aperio_metadata: Option<AperioMetadata>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+    pub level_downsamples: Vec<f64>,
+    properties: HashMap<String, String>,
+}
+
+pub struct Aperio {
+    data: Tiff,
+    levels: Vec<Level>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+    pub metadata: AperioMetadata,
+    associated_images: Vec<(String, StripedImage)>,
+}
+
+/// Leica SCN, the slide is its first main image and every main image is in `images`.
+pub struct Leica {
+    data: Tiff,
+    spec: leica::LeicaSpec,
+    macro_level: Option<TiledLevel>,
+    pub images: Vec<LeicaImage>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+    pub macro_dimensions: Option<(u64, u64)>,
+}
+
+/// Main image of a Leica collection, e.g. one of the regions scanned on a slide.
+pub struct LeicaImage {
+    levels: Vec<Level>,
+    pub name: Option<String>,
+    pub objective_power: Option<f64>,
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+}
+
+pub struct Ndpi {
+    data: Tiff,
+    levels: Vec<NdpiLevel>,
+    macro_ifd: Option<ndpi::NdpiIfd>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+    pub macro_dimensions: Option<(u64, u64)>,
+    pub source_lens: f32,
+    pub focal_plane: i32,
+    pub x_offset_from_slide_centre: i32,
+    pub y_offset_from_slide_centre: i32,
+}
+
+pub struct Philips {
+    data: Tiff,
+    levels: Vec<Level>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+}
+
+pub struct Ventana {
+    data: Tiff,
+    levels: Vec<Level>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+}
+
+/// Level of restart intervals as tiles, or a `whole` level read as one tile.
+struct NdpiLevel {
+    header: ndpi::JpegHeader,
+    tile_size: (u64, u64),
+    whole: bool,
+    t: ndpi::NdpiIfd,
+}
+
+pub struct GenericTiff {
+    data: Tiff,
+    levels: Vec<Level>,
+    pub level_count: u64,
+    pub dimensions: (u64, u64),
+    pub level_dimensions: Vec<(u64, u64)>,
+    pub level_tile_sizes: Vec<(u64, u64)>,
+    pub level_downsamples: Vec<f64>,
+}
+
+impl<S> Eozin<S> {
+    /// Level to read for `downsample` of level 0, as OpenSlide's `get_best_level_for_downsample`.
+    pub fn get_best_level_for_downsample(&self, downsample: f64) -> usize {
+        openslide::best_level_for_downsample(&self.level_downsamples, downsample)
+    }
+
+    /// Properties keyed as OpenSlide does, e.g. `openslide.mpp-x` or `tiff.ImageDescription`.
+    pub fn properties(&self) -> &HashMap<String, String> {
+        &self.properties
+    }
+
+    /// Names such as `label`, `macro` or `thumbnail`.
+    pub fn associated_image_names(&self) -> Vec<String> {
+        self.slide.associated_image_names()
+    }
+
+    /// Main images of the slide, more than one for a Leica collection of several regions.
+    /// The levels of `Eozin` are the ones of the first, e.g. [`Eozin::level_dimensions`].
+    pub fn image_count(&self) -> usize {
+        self.slide.image_count()
+    }
+
+    /// Level dimensions of the main image `image`, `None` beyond [`Eozin::image_count`].
+    pub fn image_level_dimensions(&self, image: usize) -> Option<Vec<(u64, u64)>> {
+        self.slide.image_level_dimensions(image).map(<[_]>::to_vec)
+    }
+
+    /// Keeps tiles read lately up to `budget` bytes, the least recently used ones are
+    /// dropped first. Overlapping regions, e.g. sliding windows or a panning viewer,
+    /// read and decode their shared tiles once.
+    pub fn with_cache(mut self, budget: usize, tiles: CachedTiles) -> Self {
+        self.set_cache(budget, tiles);
+        self
+    }
+
+    /// Replaces the cache by an empty one, see [`Eozin::with_cache`].
+    pub fn set_cache(&mut self, budget: usize, tiles: CachedTiles) {
+        self.cache = Some(TileCache::new(budget, tiles));
+    }
+
+    /// Hits and misses of the cache, all zero without a cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.as_ref().map(|c| c.stats()).unwrap_or_default()
+    }
+
+    pub fn clear_cache(&self) {
+        if let Some(c) = &self.cache {
+            c.clear();
+        }
+    }
+
+    /// Size of the thumbnail fitting in `max_size` and the smallest level large enough for it.
+    #[cfg(feature = "decode")]
+    fn thumbnail_plan(&self, max_size: (u64, u64)) -> ((u64, u64), usize) {
+        let (w, h) = self.level_dimensions[0];
+        let downsample = (w as f64 / max_size.0 as f64).max(h as f64 / max_size.1 as f64);
+        let size = (
+            ((w as f64 / downsample).round() as u64).max(1),
+            ((h as f64 / downsample).round() as u64).max(1),
+        );
+        let level = self
+            .level_dimensions
+            .iter()
+            .rposition(|(lw, lh)| *lw >= size.0 && *lh >= size.1)
+            .unwrap_or(0);
+        (size, level)
+    }
+}
+
+impl<S: AsyncByteSource> Eozin<S> {
+    /// Slide read from an [`AsyncByteSource`], e.g. a file or an object storage client of
+    /// an async runtime, with the IFD walker and vendors of the sync reader.
+    pub async fn open_async(source: S) -> Result<Self, EozinError> {
+        Self::open_async_with(source, &Registry::default()).await
+    }
+
+    /// Vendors of `registry` which detect the slide open it in the order of
+    /// [`Registry::candidates`], the first which finds a level wins.
+    pub async fn open_async_with(source: S, registry: &Registry) -> Result<Self, EozinError> {
+        let mut data = reader::read_tiff(&source).await?;
+        let candidates = registry.candidates(&data);
+        let aperio_metadata = aperio::metadata(&data);
+        let size = source.size().await?;
+        let (vendor, slide) = {
+            let s = SlideSource::new(source.as_bytes(), size);
+            let mut first_error = None;
+            let mut found = None;
+            for (i, vendor) in candidates.iter().enumerate() {
+                let tiff = match i + 1 < candidates.len() {
+                    true => data.clone(),
+                    false => std::mem::take(&mut data),
+                };
+                match s.run(&source, vendor.open(&s, tiff)).await {
+                    Ok(slide) if !slide.level_dimensions().is_empty() => {
+                        found = Some((*vendor, slide));
+                        break;
+                    }
+                    Ok(_) => first_error.get_or_insert(missing("level")),
+                    Err(e) => first_error.get_or_insert(e),
+                };
+            }
+            found.ok_or(first_error.unwrap_or(missing("supported vendor")))?
+        };
+        let level_dimensions = slide.level_dimensions().to_vec();
+        let dimensions = level_dimensions[0];
+        let base = aperio_metadata
+            .as_ref()
+            .and_then(|m| m.original_dimensions)
+            .unwrap_or(dimensions);
+        let level_downsamples = openslide::level_downsamples(base, &level_dimensions);
+        let mut properties = openslide::level_properties(
+            vendor.name(),
+            &level_dimensions,
+            slide.level_tile_sizes(),
+            &level_downsamples,
+        );
+        properties.extend(slide.properties());
+        Ok(Eozin {
+            level_downsamples,
+            properties,
+            vendor: vendor.name().to_string(),
+            aperio_metadata,
+            level_count: level_dimensions.len() as u64,
+            dimensions,
+            level_tile_sizes: slide.level_tile_sizes().to_vec(),
+            level_dimensions,
+            slide,
+            source,
+            size,
+            cache: None,
+        })
+    }
+
+    /// Tile as it's stored, shared with the cache so a cached tile isn't copied.
+    pub async fn read_tile_async(
+        &self,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Arc<Tile>, EozinError> {
+        if let Some(tile) = self.cache.as_ref().and_then(|c| c.raw(lv, x, y)) {
+            return Ok(tile);
+        }
+        let s = self.slide_source();
+        let tile = s
+            .run(&self.source, self.slide.read_tile(&s, lv, x, y))
+            .await?;
+        let tile = Arc::new(tile);
+        if let Some(c) = &self.cache {
+            c.insert_raw(lv, x, y, tile.clone());
+        }
+        Ok(tile)
+    }
+
+    /// Tile of the main image `image`, see [`Eozin::image_count`]. The first image is
+    /// the one of [`Eozin::read_tile_async`], tiles of other images aren't cached.
+    pub async fn read_image_tile_async(
+        &self,
+        image: usize,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Arc<Tile>, EozinError> {
+        if image == 0 {
+            return self.read_tile_async(lv, x, y).await;
+        }
+        let s = self.slide_source();
+        s.run(
+            &self.source,
+            self.slide.read_image_tile(&s, image, lv, x, y),
+        )
+        .await
+        .map(Arc::new)
+    }
+
+    #[cfg(feature = "decode")]
+    async fn decoded_tile_async(
+        &self,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Arc<RgbImage>, EozinError> {
+        if let Some(img) = self.cache.as_ref().and_then(|c| c.decoded(lv, x, y)) {
+            return Ok(img);
+        }
+        let img = Arc::new(self.read_tile_async(lv, x, y).await?.decode()?);
+        if let Some(c) = &self.cache {
+            c.insert_decoded(lv, x, y, img.clone());
+        }
+        Ok(img)
+    }
+
+    /// Async [`Eozin::read_region`].
+    #[cfg(feature = "decode")]
+    pub async fn read_region_async(
+        &self,
+        location: (i64, i64),
+        level: usize,
+        size: (u64, u64),
+    ) -> Result<RgbImage, EozinError> {
+        let dimensions = *self.level_dimensions.get(level).ok_or(missing("level"))?;
+        let (tw, th) = *self.level_tile_sizes.get(level).ok_or(missing("level"))?;
+        let region = Region::new(location, self.level_downsamples[level], size)?;
+        let mut canvas = region.canvas()?;
+        for (x, y) in region.tiles(dimensions, (tw, th)) {
+            let tile = self
+                .decoded_tile_async(level, x as usize, y as usize)
+                .await?;
+            region.paint(&mut canvas, dimensions, (x * tw, y * th), &tile);
+        }
+        Ok(canvas)
+    }
+
+    /// Async [`Eozin::get_thumbnail`]. The level is resized tile by tile, so it's read
+    /// however large it is.
+    #[cfg(feature = "decode")]
+    pub async fn get_thumbnail_async(&self, max_size: (u64, u64)) -> Result<RgbImage, EozinError> {
+        let (size, level) = self.thumbnail_plan(max_size);
+        let (lw, lh) = self.level_dimensions[level];
+        if self
+            .associated_image_names()
+            .iter()
+            .any(|n| n == "thumbnail")
+        {
+            // a thumbnail which doesn't decode leaves the pyramid to read
+            let thumbnail = self
+                .read_associated_image_async("thumbnail")
+                .await
+                .and_then(|t| t.decode());
+            if let Ok(thumbnail) = thumbnail {
+                if thumbnail.width >= size.0 && thumbnail.width < lw && thumbnail.height >= size.1 {
+                    let (tw, th) = (thumbnail.width, thumbnail.height);
+                    let r = Region::new((0, 0), 1.0, (tw, th))?;
+                    let mut canvas = r.canvas()?;
+                    r.paint(&mut canvas, (tw, th), (0, 0), &thumbnail);
+                    return region::resize_area(&canvas, size.0, size.1);
+                }
+            }
+        }
+        let (tw, th) = self.level_tile_sizes[level];
+        let whole = Region::new((0, 0), 1.0, (lw, lh))?;
+        let mut resize = region::AreaResize::new((lw, lh), size, 4)?;
+        for (x, y) in whole.tiles((lw, lh), (tw, th)) {
+            let tile = self
+                .decoded_tile_async(level, x as usize, y as usize)
+                .await?;
+            let (ox, oy) = (x * tw, y * th);
+            let r = Region::new((ox as i64, oy as i64), 1.0, (tw, th))?;
+            let mut canvas = r.canvas()?;
+            r.paint(&mut canvas, (lw, lh), (ox, oy), &tile);
+            resize.add((ox, oy), &canvas);
+        }
+        Ok(resize.finish())
+    }
+
+    pub async fn read_associated_image_async(&self, name: &str) -> Result<Tile, EozinError> {
+        let s = self.slide_source();
+        s.run(&self.source, self.slide.read_associated_image(&s, name))
+            .await
+    }
+
+    /// Source the slide reads, its reads are answered from `self.source`.
+    fn slide_source(&self) -> SlideSource<'_> {
+        SlideSource::new(self.source.as_bytes(), self.size)
+    }
+}
+
+impl Aperio {
+    pub fn new(data: Tiff) -> Result<Self, EozinError> {
+        let mut levels = Vec::new();
+        let mut level_dimensions = Vec::new();
+        let mut level_tile_sizes = Vec::new();
+        let mut maybe_dimensions = None;
+        let mut associated_images = Vec::new();
+        for (i, ifd) in data.iter().enumerate() {
+            if let Some(lv) = TiledLevel::new(ifd) {
+                level_dimensions.push((lv.t.width, lv.t.height));
+                level_tile_sizes.push((lv.t.tile_width, lv.t.tile_height));
+                maybe_dimensions = maybe_dimensions.or(Some((lv.t.width, lv.t.height)));
+                levels.push(Level::Tiled(lv));
+            } else if let Some(name) = aperio::associated_image_name(i, ifd) {
+                if let Some(img) = StripedImage::new(ifd) {
+                    associated_images.push((name.to_string(), img));
+                }
+            }
+        }
+        if let Some(dimensions) = maybe_dimensions {
+            Ok(Aperio {
+                associated_images,
+                metadata: aperio::metadata(&data).unwrap_or_default(),
+                data,
+                levels,
+                dimensions,
+                level_count: level_dimensions.len() as u64,
+                level_dimensions,
+                level_tile_sizes,
+            })
+        } else {
+            Err(EozinError::new("Couldn't find any IFD in the input file"))
+        }
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        level::read_tile(&self.levels, source, lv, x, y).await
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut p = tiff_properties(&self.data);
+        p.extend(aperio::properties(&self.metadata));
+        p
+    }
+
+    pub fn associated_image_names(&self) -> Vec<String> {
+        self.associated_images
+            .iter()
+            .map(|(n, _)| n.clone())
+            .collect()
+    }
+
+    /// JPEG for JPEG compressed images, decompressed samples otherwise.
+    pub async fn read_associated_image<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        name: &str,
+    ) -> Result<Tile, EozinError> {
+        let (_, img) = self
+            .associated_images
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or(missing("associated image"))?;
+        img.read(source).await
+    }
+}
+
+impl Leica {
+    pub fn new(data: Tiff) -> Result<Self, EozinError> {
+        let spec = leica::parse(&data)?;
+        let mut images = Vec::new();
+        for image in spec.main_images() {
+            let mut levels = Vec::new();
+            for d in image.levels() {
+                let lv = data
+                    .get(d.ifd)
+                    .and_then(TiledLevel::new)
+                    .ok_or(missing(&format!("tiled IFD {}", d.ifd)))?;
+                levels.push(Level::Tiled(lv));
+            }
+            images.push(LeicaImage {
+                name: image.name.clone(),
+                objective_power: image.objective,
+                level_dimensions: levels.iter().map(Level::dimensions).collect(),
+                level_tile_sizes: levels.iter().map(Level::tile_size).collect(),
+                levels,
+            });
+        }
+        let macro_level = spec
+            .macro_image()
+            .and_then(|img| img.levels().first().and_then(|d| data.get(d.ifd)))
+            .and_then(TiledLevel::new);
+        let main = images.first().ok_or(missing("main image"))?;
+        let level_dimensions = main.level_dimensions.clone();
+        let level_tile_sizes = main.level_tile_sizes.clone();
+        let dimensions = *level_dimensions.first().ok_or(missing("level"))?;
+        Ok(Leica {
+            macro_dimensions: macro_level.as_ref().map(|lv| (lv.t.width, lv.t.height)),
+            data,
+            spec,
+            images,
+            macro_level,
+            dimensions,
+            level_count: level_dimensions.len() as u64,
+            level_dimensions,
+            level_tile_sizes,
+        })
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        self.read_image_tile(source, 0, lv, x, y).await
+    }
+
+    /// Tile of level `lv` of the main image `image` of [`Leica::images`].
+    pub async fn read_image_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        image: usize,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        let image = self.images.get(image).ok_or(missing("main image"))?;
+        level::read_tile(&image.levels, source, lv, x, y).await
+    }
+
+    pub fn barcode(&self) -> Option<&str> {
+        self.spec.barcode.as_deref()
+    }
+
+    pub fn objective_power(&self) -> Option<f64> {
+        self.spec.main_image().and_then(|img| img.objective)
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut p = tiff_properties(&self.data);
+        p.extend(self.spec.properties());
+        p
+    }
+
+    pub async fn read_macro_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        let lv = self.macro_level.as_ref().ok_or(missing("macro image"))?;
+        lv.read_tile(source, x, y).await
+    }
+
+    pub fn associated_image_names(&self) -> Vec<String> {
+        match self.macro_level {
+            Some(_) => vec!["macro".to_string()],
+            None => vec![],
+        }
+    }
+
+    /// Macro of a single tile as it's stored, tiles of a larger one are painted into
+    /// RGBA samples.
+    pub async fn read_associated_image<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        name: &str,
+    ) -> Result<Tile, EozinError> {
+        let lv = match name {
+            "macro" => self.macro_level.as_ref().ok_or(missing("macro image"))?,
+            _ => return Err(missing("associated image")),
+        };
+        let (w, h) = (lv.t.width, lv.t.height);
+        if lv.t.tile_width >= w && lv.t.tile_height >= h {
+            return lv.read_tile(source, 0, 0).await;
+        }
+        #[cfg(feature = "decode")]
+        {
+            let (tw, th) = (lv.t.tile_width, lv.t.tile_height);
+            let r = Region::new((0, 0), 1.0, (w, h))?;
+            let mut canvas = r.canvas()?;
+            for (x, y) in r.tiles((w, h), (tw, th)) {
+                let tile = lv.read_tile(source, x as usize, y as usize).await?;
+                r.paint(&mut canvas, (w, h), (x * tw, y * th), &tile.decode()?);
+            }
+            Ok(Tile::Raw {
+                width: w,
+                height: h,
+                samples_per_pixel: canvas.channels,
+                data: canvas.data,
+            })
+        }
+        #[cfg(not(feature = "decode"))]
+        Err(EozinError::new(
+            "Enable decode feature to read a macro image of several tiles",
+        ))
+    }
+}
+
+impl Ndpi {
+    pub async fn new<S: AsyncByteSource + ?Sized>(
+        source: &S,
+        data: Tiff,
+    ) -> Result<Self, EozinError> {
+        let ifds: Vec<ndpi::NdpiIfd> = data.iter().filter_map(ndpi::ndpi_ifd).collect();
+        let mut levels = Vec::new();
+        for t in ifds.iter().filter(|t| t.is_level()) {
+            let header_len = *t.mcu_starts.first().ok_or(missing("NDPI MCU starts"))?;
+            let buf = source::read_bytes(
+                source,
+                t.strip_offset,
+                t.strip_offset.saturating_add(header_len),
+            )
+            .await?;
+            let header = ndpi::JpegHeader::parse(&buf).ok_or(missing("NDPI jpeg header"))?;
+            let (tile_size, whole) = match header.tile_size(t.width) {
+                Some(size) => (size, false),
+                // restart intervals which don't tile the level leave it a single JPEG
+                None if t.width <= u16::MAX as u64 && t.height <= u16::MAX as u64 => {
+                    ((t.width, t.height), true)
+                }
+                None => return Err(missing("NDPI restart interval within the level")),
+            };
+            levels.push(NdpiLevel {
+                header,
+                tile_size,
+                whole,
+                t: t.clone(),
+            });
+        }
+        levels.sort_by_key(|lv| std::cmp::Reverse(lv.t.width));
+        let base = levels.first().ok_or(missing("level"))?;
+        let macro_ifd = ifds.iter().find(|t| t.is_macro()).cloned();
+        Ok(Ndpi {
+            dimensions: (base.t.width, base.t.height),
+            source_lens: base.t.source_lens,
+            focal_plane: base.t.focal_plane,
+            x_offset_from_slide_centre: base.t.x_offset_from_slide_centre,
+            y_offset_from_slide_centre: base.t.y_offset_from_slide_centre,
+            level_count: levels.len() as u64,
+            level_dimensions: levels.iter().map(|lv| (lv.t.width, lv.t.height)).collect(),
+            level_tile_sizes: levels.iter().map(|lv| lv.tile_size).collect(),
+            macro_dimensions: macro_ifd.as_ref().map(|t| (t.width, t.height)),
+            macro_ifd,
+            data,
+            levels,
+        })
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        let lv = self.levels.get(lv).ok_or(missing("level"))?;
+        let (tile_width, _) = lv.tile_size;
+        let num_tiles_across = lv.t.width.div_ceil(tile_width) as usize;
+        if x >= num_tiles_across || (lv.whole && y > 0) {
+            return Err(missing("selected tile is out of index"));
+        }
+        let tile_id = num_tiles_across
+            .checked_mul(y)
+            .and_then(|i| i.checked_add(x))
+            .ok_or(missing("selected tile is out of index"))?;
+        let start = *lv
+            .t
+            .mcu_starts
+            .get(tile_id)
+            .ok_or(missing("selected tile is out of index"))?;
+        let end = *lv
+            .t
+            .mcu_starts
+            .get(tile_id + 1)
+            .filter(|_| !lv.whole)
+            .unwrap_or(&lv.t.strip_byte_count);
+        let jpeg = source::with_bytes(
+            source,
+            lv.t.strip_offset.saturating_add(start),
+            lv.t.strip_offset.saturating_add(end),
+            |buf| lv.header.virtual_tile(lv.tile_size, buf),
+        )
+        .await?;
+        Ok(Tile::Jpeg(jpeg))
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut p = tiff_properties(&self.data);
+        if let Some(lv) = self.levels.first() {
+            p.extend(lv.t.properties());
+        }
+        p
+    }
+
+    pub async fn read_macro_image<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+    ) -> Result<Tile, EozinError> {
+        let t = self.macro_ifd.as_ref().ok_or(missing("macro image"))?;
+        let end = t.strip_offset.saturating_add(t.strip_byte_count);
+        let buf = source::read_bytes(source, t.strip_offset, end).await?;
+        Ok(Tile::Jpeg(buf))
+    }
+
+    pub fn associated_image_names(&self) -> Vec<String> {
+        match self.macro_ifd {
+            Some(_) => vec!["macro".to_string()],
+            None => vec![],
+        }
+    }
+
+    pub async fn read_associated_image<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        name: &str,
+    ) -> Result<Tile, EozinError> {
+        match name {
+            "macro" => self.read_macro_image(source).await,
+            _ => Err(missing("associated image")),
+        }
+    }
+}
+
+impl Philips {
+    pub fn new(data: Tiff) -> Result<Self, EozinError> {
+        let levels: Vec<Level> = data
+            .iter()
+            .filter(|ifd| philips::is_level(ifd))
+            .filter_map(TiledLevel::new)
+            .map(Level::Tiled)
+            .collect();
+        let level_dimensions: Vec<(u64, u64)> = levels.iter().map(Level::dimensions).collect();
+        Ok(Philips {
+            dimensions: *level_dimensions.first().ok_or(missing("level"))?,
+            level_count: levels.len() as u64,
+            level_tile_sizes: levels.iter().map(Level::tile_size).collect(),
+            level_dimensions,
+            data,
+            levels,
+        })
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        level::read_tile(&self.levels, source, lv, x, y).await
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut p = tiff_properties(&self.data);
+        p.extend(philips::properties(&self.data));
+        p
+    }
+}
+
+impl Ventana {
+    pub fn new(data: Tiff) -> Result<Self, EozinError> {
+        let mut levels: Vec<(u64, TiledLevel)> = data
+            .iter()
+            .filter_map(|ifd| ventana::level(ifd).zip(TiledLevel::new(ifd)))
+            .collect();
+        levels.sort_by_key(|(n, _)| *n);
+        let levels: Vec<Level> = levels.into_iter().map(|(_, lv)| Level::Tiled(lv)).collect();
+        let level_dimensions: Vec<(u64, u64)> = levels.iter().map(Level::dimensions).collect();
+        Ok(Ventana {
+            dimensions: *level_dimensions.first().ok_or(missing("level"))?,
+            level_count: levels.len() as u64,
+            level_tile_sizes: levels.iter().map(Level::tile_size).collect(),
+            level_dimensions,
+            data,
+            levels,
+        })
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        level::read_tile(&self.levels, source, lv, x, y).await
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut p = tiff_properties(&self.data);
+        p.extend(ventana::properties(&self.data));
+        p
+    }
+}
+
+impl GenericTiff {
+    /// Levels are the IFDs of the chain and their SubIFDs, where e.g. OME-TIFF keeps
+    /// the reduced resolutions.
+    pub fn new(data: Tiff) -> Result<Self, EozinError> {
+        let ifds = || {
+            data.iter()
+                .flat_map(|ifd| std::iter::once(ifd).chain(property::sub_ifds(ifd, tag::SubIFDs)))
+        };
+        let mut levels: Vec<Level> = ifds()
+            .filter_map(TiledLevel::new)
+            .map(Level::Tiled)
+            .collect();
+        if levels.is_empty() {
+            levels = ifds()
+                .filter_map(StripedImage::new)
+                .map(Level::Striped)
+                .collect();
+        }
+        levels.sort_by_key(|lv| {
+            let (w, h) = lv.dimensions();
+            std::cmp::Reverse(w.saturating_mul(h))
+        });
+        let level_dimensions: Vec<(u64, u64)> = levels.iter().map(Level::dimensions).collect();
+        let dimensions = *level_dimensions.first().ok_or(missing("level"))?;
+        Ok(GenericTiff {
+            level_downsamples: openslide::level_downsamples(dimensions, &level_dimensions),
+            dimensions,
+            level_count: levels.len() as u64,
+            level_tile_sizes: levels.iter().map(Level::tile_size).collect(),
+            level_dimensions,
+            data,
+            levels,
+        })
+    }
+
+    pub async fn read_tile<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Tile, EozinError> {
+        level::read_tile(&self.levels, source, lv, x, y).await
+    }
+
+    pub fn properties(&self) -> HashMap<String, String> {
+        tiff_properties(&self.data)
+    }
+}
+
+macro_rules! impl_slide {
+    (associated: $($t:ty),*) => {$(
+        impl Slide for $t {
+            impl_slide!(@common $t);
+            fn associated_image_names(&self) -> Vec<String> {
+                <$t>::associated_image_names(self)
+            }
+            fn read_associated_image<'a>(
+                &'a self,
+                source: &'a SlideSource<'_>,
+                name: &'a str,
+            ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+                Box::pin(<$t>::read_associated_image(self, source, name))
+            }
+        }
+    )*};
+    ($($t:ty),*) => {$(
+        impl Slide for $t {
+            impl_slide!(@common $t);
+        }
+    )*};
+    (@common $t:ty) => {
+        fn level_dimensions(&self) -> &[(u64, u64)] {
+            &self.level_dimensions
+        }
+        fn level_tile_sizes(&self) -> &[(u64, u64)] {
+            &self.level_tile_sizes
+        }
+        fn read_tile<'a>(
+            &'a self,
+            source: &'a SlideSource<'_>,
+            lv: usize,
+            x: usize,
+            y: usize,
+        ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+            Box::pin(<$t>::read_tile(self, source, lv, x, y))
+        }
+        fn properties(&self) -> HashMap<String, String> {
+            <$t>::properties(self)
+        }
+    };
+}
+
+impl_slide!(associated: Aperio, Ndpi);
+impl_slide!(Philips, Ventana, GenericTiff);
+
+impl Slide for Leica {
+    impl_slide!(@common Leica);
+    fn image_count(&self) -> usize {
+        self.images.len()
+    }
+    fn image_level_dimensions(&self, image: usize) -> Option<&[(u64, u64)]> {
+        self.images.get(image).map(|img| &img.level_dimensions[..])
+    }
+    fn read_image_tile<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        image: usize,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+        Box::pin(Leica::read_image_tile(self, source, image, lv, x, y))
+    }
+    fn associated_image_names(&self) -> Vec<String> {
+        Leica::associated_image_names(self)
+    }
+    fn read_associated_image<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+        Box::pin(Leica::read_associated_image(self, source, name))
+    }
+}
+
+/// `tiff.*` of the first IFD with mpp from its resolution, vendor properties override them.
+fn tiff_properties(data: &Tiff) -> HashMap<String, String> {
+    let mut p = HashMap::new();
+    if let Some(ifd) = data.first() {
+        p.extend(openslide::tiff_properties(ifd));
+        if let Some(mpp) = openslide::resolution_mpp(ifd) {
+            openslide::mpp_properties(&mut p, mpp);
+        }
+    }
+    p
+}
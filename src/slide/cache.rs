@@ -0,0 +1,224 @@
+//! Tiles read lately, kept up to a byte budget so overlapping regions don't read
+//! and decode the same tiles again.
+#[cfg(feature = "decode")]
+use super::RgbImage;
+use super::Tile;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Tiles kept by the cache of [`Eozin::with_cache`](super::Eozin::with_cache).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CachedTiles {
+    /// Tiles as `read_tile` returns them, still compressed.
+    Raw,
+    /// Pixels decoded by `read_region`, larger but nothing to decode again.
+    Decoded,
+    Both,
+}
+
+/// Lookups since the cache was set, a region tile missing from both kinds counts twice.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Kind {
+    Raw,
+    #[cfg(feature = "decode")]
+    Decoded,
+}
+
+type Key = (Kind, usize, usize, usize);
+
+#[derive(Clone)]
+enum Entry {
+    Raw(Arc<Tile>),
+    #[cfg(feature = "decode")]
+    Decoded(Arc<RgbImage>),
+}
+
+impl Entry {
+    fn bytes(&self) -> usize {
+        match self {
+            Entry::Raw(t) => t.buffer().len(),
+            #[cfg(feature = "decode")]
+            Entry::Decoded(img) => img.data.len(),
+        }
+    }
+}
+
+pub(crate) struct TileCache {
+    budget: usize,
+    tiles: CachedTiles,
+    lru: Mutex<Lru>,
+}
+
+/// Entries stamped by their last use, the smallest stamp is dropped first.
+#[derive(Default)]
+struct Lru {
+    map: HashMap<Key, (u64, Entry)>,
+    order: BTreeMap<u64, Key>,
+    clock: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl Lru {
+    fn stamp(&mut self, key: Key) -> u64 {
+        self.clock += 1;
+        self.order.insert(self.clock, key);
+        self.clock
+    }
+
+    fn get(&mut self, key: Key) -> Option<Entry> {
+        let Some((stamp, entry)) = self.map.get(&key).cloned() else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.order.remove(&stamp);
+        let stamp = self.stamp(key);
+        self.map.insert(key, (stamp, entry.clone()));
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry, budget: usize) {
+        let bytes = entry.bytes();
+        if bytes > budget {
+            return;
+        }
+        let stamp = self.stamp(key);
+        if let Some((old, e)) = self.map.insert(key, (stamp, entry)) {
+            self.order.remove(&old);
+            self.bytes -= e.bytes();
+        }
+        self.bytes += bytes;
+        while self.bytes > budget {
+            let Some((_, k)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((_, e)) = self.map.remove(&k) {
+                self.bytes -= e.bytes();
+            }
+        }
+    }
+}
+
+impl TileCache {
+    pub(crate) fn new(budget: usize, tiles: CachedTiles) -> Self {
+        TileCache {
+            budget,
+            tiles,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    fn keeps_raw(&self) -> bool {
+        self.tiles != CachedTiles::Decoded
+    }
+
+    pub(crate) fn raw(&self, lv: usize, x: usize, y: usize) -> Option<Arc<Tile>> {
+        if !self.keeps_raw() {
+            return None;
+        }
+        match self.lock().get((Kind::Raw, lv, x, y))? {
+            Entry::Raw(t) => Some(t),
+            #[cfg(feature = "decode")]
+            Entry::Decoded(_) => None,
+        }
+    }
+
+    pub(crate) fn insert_raw(&self, lv: usize, x: usize, y: usize, tile: Arc<Tile>) {
+        if self.keeps_raw() {
+            self.lock()
+                .insert((Kind::Raw, lv, x, y), Entry::Raw(tile), self.budget);
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    fn keeps_decoded(&self) -> bool {
+        self.tiles != CachedTiles::Raw
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn decoded(&self, lv: usize, x: usize, y: usize) -> Option<Arc<RgbImage>> {
+        if !self.keeps_decoded() {
+            return None;
+        }
+        match self.lock().get((Kind::Decoded, lv, x, y))? {
+            Entry::Decoded(img) => Some(img),
+            Entry::Raw(_) => None,
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn insert_decoded(&self, lv: usize, x: usize, y: usize, img: Arc<RgbImage>) {
+        if self.keeps_decoded() {
+            self.lock()
+                .insert((Kind::Decoded, lv, x, y), Entry::Decoded(img), self.budget);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let lru = self.lock();
+        CacheStats {
+            hits: lru.hits,
+            misses: lru.misses,
+            entries: lru.map.len(),
+            bytes: lru.bytes,
+        }
+    }
+
+    /// Drops every tile, the counts of lookups are kept.
+    pub(crate) fn clear(&self) {
+        let mut lru = self.lock();
+        lru.map.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests_cache {
+    use super::*;
+
+    fn raw(n: usize) -> Arc<Tile> {
+        Arc::new(Tile::Jpeg(vec![0; n]))
+    }
+
+    #[test]
+    fn test_tile_cache() {
+        let cache = TileCache::new(100, CachedTiles::Raw);
+        assert!(cache.raw(0, 0, 0).is_none());
+        cache.insert_raw(0, 0, 0, raw(40));
+        cache.insert_raw(0, 1, 0, raw(40));
+        assert_eq!(cache.raw(0, 0, 0).map(|t| t.buffer().len()), Some(40));
+        // (0, 1, 0) is the least recently used one
+        cache.insert_raw(0, 2, 0, raw(40));
+        assert!(cache.raw(0, 1, 0).is_none());
+        assert!(cache.raw(0, 2, 0).is_some());
+        cache.insert_raw(1, 0, 0, raw(101));
+        assert!(cache.raw(1, 0, 0).is_none());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 3,
+                entries: 2,
+                bytes: 80,
+            }
+        );
+        cache.clear();
+        assert_eq!(cache.stats().bytes, 0);
+        assert!(cache.raw(0, 0, 0).is_none());
+    }
+}
@@ -0,0 +1,324 @@
+use super::{Aperio, EozinError, GenericTiff, Leica, Ndpi, Philips, Tile, Ventana};
+use crate::error::missing;
+use crate::source::{BoxFuture, SlideSource};
+use crate::tiff::Tiff;
+use crate::vendor::{aperio, generic, leica, ndpi, philips, ventana};
+use std::collections::HashMap;
+
+/// How sure a [`Vendor`] is that it can read a given tiff.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Confidence {
+    Unsupported,
+    /// Readable, but nothing vendor specific was found.
+    Low,
+    /// Vendor specific tags or descriptions were found.
+    High,
+}
+
+/// A whole slide image opened by a [`Vendor`], tiles are read through `&self` from any thread.
+/// Bytes are read from the `source` given to every read, which forwards them from the
+/// source of the `Eozin`, so the same reads serve sync and async slides.
+pub trait Slide: Send + Sync {
+    fn level_dimensions(&self) -> &[(u64, u64)];
+    fn level_tile_sizes(&self) -> &[(u64, u64)];
+    fn read_tile<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> BoxFuture<'a, Result<Tile, EozinError>>;
+    /// Main images of the slide, e.g. the regions of a Leica collection. The levels and
+    /// tiles above are the ones of the first.
+    fn image_count(&self) -> usize {
+        1
+    }
+    fn image_level_dimensions(&self, image: usize) -> Option<&[(u64, u64)]> {
+        (image == 0).then(|| self.level_dimensions())
+    }
+    fn read_image_tile<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        image: usize,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+        match image {
+            0 => self.read_tile(source, lv, x, y),
+            _ => Box::pin(std::future::ready(Err(missing("main image")))),
+        }
+    }
+    /// Vendor and `tiff.*` properties, `openslide.level*` ones are added by `Eozin`.
+    fn properties(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+    /// Names of the label, macro or thumbnail images stored next to the pyramid.
+    fn associated_image_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn read_associated_image<'a>(
+        &'a self,
+        _source: &'a SlideSource<'_>,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<Tile, EozinError>> {
+        let e = EozinError::new(&format!("No associated image {}", name));
+        Box::pin(std::future::ready(Err(e)))
+    }
+}
+
+/// Detects and opens one slide format, register it to a [`Registry`] to extend `Eozin`.
+pub trait Vendor: Send + Sync {
+    fn name(&self) -> &str;
+    fn detect(&self, tiff: &Tiff) -> Confidence;
+    fn open<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>>;
+}
+
+/// Ordered list of vendors, the most confident one is tried first and ties go to the
+/// earlier one.
+pub struct Registry {
+    vendors: Vec<Box<dyn Vendor>>,
+}
+
+impl Registry {
+    /// Registry without any vendor.
+    pub fn new() -> Self {
+        Registry {
+            vendors: Vec::new(),
+        }
+    }
+
+    /// Registered vendor is tried before every vendor registered so far.
+    pub fn register<V: Vendor + 'static>(&mut self, vendor: V) {
+        self.vendors.insert(0, Box::new(vendor));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.vendors.iter().map(|v| v.name()).collect()
+    }
+
+    pub fn detect(&self, tiff: &Tiff) -> Option<&dyn Vendor> {
+        self.candidates(tiff).into_iter().next()
+    }
+
+    /// Every vendor which can read `tiff`, the most confident first and ties in the
+    /// registered order, `Eozin` tries the next one when a vendor fails to open it.
+    pub fn candidates(&self, tiff: &Tiff) -> Vec<&dyn Vendor> {
+        let mut found: Vec<(&dyn Vendor, Confidence)> = self
+            .vendors
+            .iter()
+            .map(|v| (v.as_ref(), v.detect(tiff)))
+            .filter(|(_, c)| *c > Confidence::Unsupported)
+            .collect();
+        found.sort_by_key(|(_, c)| std::cmp::Reverse(*c));
+        found.into_iter().map(|(v, _)| v).collect()
+    }
+}
+
+impl Default for Registry {
+    /// Leica, Aperio, Philips, Ventana, NDPI and generic tiled or striped tiff in this order.
+    fn default() -> Self {
+        Registry {
+            vendors: vec![
+                Box::new(LeicaVendor),
+                Box::new(AperioVendor),
+                Box::new(PhilipsVendor),
+                Box::new(VentanaVendor),
+                Box::new(NdpiVendor),
+                Box::new(GenericTiffVendor),
+            ],
+        }
+    }
+}
+
+struct LeicaVendor;
+struct AperioVendor;
+struct PhilipsVendor;
+struct VentanaVendor;
+struct NdpiVendor;
+struct GenericTiffVendor;
+
+impl Vendor for LeicaVendor {
+    fn name(&self) -> &str {
+        "leica"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        high_if(leica::is_compatible(tiff))
+    }
+    fn open<'a>(
+        &'a self,
+        _: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        opened(Leica::new(tiff))
+    }
+}
+
+impl Vendor for AperioVendor {
+    fn name(&self) -> &str {
+        "aperio"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        high_if(aperio::is_compatible(tiff))
+    }
+    fn open<'a>(
+        &'a self,
+        _: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        opened(Aperio::new(tiff))
+    }
+}
+
+impl Vendor for PhilipsVendor {
+    fn name(&self) -> &str {
+        "philips"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        high_if(philips::is_compatible(tiff))
+    }
+    fn open<'a>(
+        &'a self,
+        _: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        opened(Philips::new(tiff))
+    }
+}
+
+impl Vendor for VentanaVendor {
+    fn name(&self) -> &str {
+        "ventana"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        high_if(ventana::is_compatible(tiff))
+    }
+    fn open<'a>(
+        &'a self,
+        _: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        opened(Ventana::new(tiff))
+    }
+}
+
+impl Vendor for NdpiVendor {
+    fn name(&self) -> &str {
+        "hamamatsu"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        high_if(ndpi::is_compatible(tiff))
+    }
+    fn open<'a>(
+        &'a self,
+        source: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        Box::pin(async move { Ok(Box::new(Ndpi::new(source, tiff).await?) as Box<dyn Slide>) })
+    }
+}
+
+impl Vendor for GenericTiffVendor {
+    fn name(&self) -> &str {
+        "generic-tiff"
+    }
+    fn detect(&self, tiff: &Tiff) -> Confidence {
+        if generic::is_compatible(tiff) {
+            Confidence::Low
+        } else {
+            Confidence::Unsupported
+        }
+    }
+    fn open<'a>(
+        &'a self,
+        _: &'a SlideSource<'_>,
+        tiff: Tiff,
+    ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+        opened(GenericTiff::new(tiff))
+    }
+}
+
+/// Slide opened from the IFDs alone.
+fn opened<'a, T: Slide + 'static>(
+    slide: Result<T, EozinError>,
+) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+    Box::pin(std::future::ready(
+        slide.map(|s| Box::new(s) as Box<dyn Slide>),
+    ))
+}
+
+fn high_if(b: bool) -> Confidence {
+    if b {
+        Confidence::High
+    } else {
+        Confidence::Unsupported
+    }
+}
+
+#[cfg(test)]
+mod tests_registry {
+    use super::*;
+    use crate::tiff::{tag, Data};
+    use std::collections::HashMap;
+
+    struct InHouse;
+
+    impl Vendor for InHouse {
+        fn name(&self) -> &str {
+            "in-house"
+        }
+        fn detect(&self, _: &Tiff) -> Confidence {
+            Confidence::High
+        }
+        fn open<'a>(
+            &'a self,
+            _: &'a SlideSource<'_>,
+            _: Tiff,
+        ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+            Box::pin(async { Err(EozinError::new("not implemented")) })
+        }
+    }
+
+    fn described(desc: &str) -> Tiff {
+        let mut ifd = HashMap::new();
+        ifd.insert(tag::ImageDescription, Data::Ascii(desc.to_string()));
+        ifd.insert(tag::TileOffsets, Data::LongVec(vec![0]));
+        vec![ifd]
+    }
+
+    #[test]
+    fn test_detect() {
+        let registry = Registry::default();
+        let aperio = described("Aperio Image Library v10.0.50\r\n|AppMag = 20");
+        assert_eq!(registry.detect(&aperio).map(|v| v.name()), Some("aperio"));
+        let unknown = described("libvips");
+        assert_eq!(
+            registry.detect(&unknown).map(|v| v.name()),
+            Some("generic-tiff")
+        );
+        assert!(Registry::new().detect(&unknown).is_none());
+        let names = |t| -> Vec<&str> { registry.candidates(t).iter().map(|v| v.name()).collect() };
+        assert_eq!(names(&aperio), ["aperio", "generic-tiff"]);
+        assert_eq!(names(&unknown), ["generic-tiff"]);
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = Registry::default();
+        registry.register(InHouse);
+        let aperio = described("Aperio Image Library v10.0.50");
+        assert_eq!(registry.detect(&aperio).map(|v| v.name()), Some("in-house"));
+        assert_eq!(registry.names()[..2], ["in-house", "leica"]);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn shared<T: Send + Sync>() {}
+        shared::<super::super::Eozin>();
+        shared::<Box<dyn Slide>>();
+    }
+}
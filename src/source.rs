@@ -0,0 +1,498 @@
+//! Where the bytes of a tiff come from. The IFD walker and the level readers are
+//! written once against [`AsyncByteSource`], every [`ByteSource`] is one as well.
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
+use crate::error::EozinError;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+#[cfg(any(feature = "http", feature = "wasm"))]
+pub(crate) mod http;
+#[cfg(feature = "http")]
+pub use self::http::HttpSource;
+
+/// Random access bytes read through `&self`, so one source is shared among threads.
+pub trait ByteSource: Send + Sync {
+    /// Exactly `len` bytes at `offset`, `UnexpectedEof` if the source is shorter.
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+    /// Total length in bytes.
+    fn size(&self) -> io::Result<u64>;
+    /// Whole source if it is in memory or mapped, tiles are then borrowed instead of copied.
+    fn as_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Source whose reads wait on something else, e.g. a `Blob` or a network request.
+pub trait AsyncByteSource {
+    /// Exactly `len` bytes at `offset`, `UnexpectedEof` if the source is shorter.
+    fn read_at(&self, offset: u64, len: usize) -> impl Future<Output = io::Result<Vec<u8>>>;
+    /// Total length in bytes.
+    fn size(&self) -> impl Future<Output = io::Result<u64>>;
+    /// Whole source if it is in memory, see [`ByteSource::as_bytes`].
+    fn as_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+macro_rules! impl_async_for_sync {
+    () => {
+        fn read_at(&self, offset: u64, len: usize) -> impl Future<Output = io::Result<Vec<u8>>> {
+            std::future::ready(ByteSource::read_at(self, offset, len))
+        }
+
+        fn size(&self) -> impl Future<Output = io::Result<u64>> {
+            std::future::ready(ByteSource::size(self))
+        }
+
+        fn as_bytes(&self) -> Option<&[u8]> {
+            ByteSource::as_bytes(self)
+        }
+    };
+}
+
+impl<T: ByteSource> AsyncByteSource for T {
+    impl_async_for_sync!();
+}
+
+impl AsyncByteSource for dyn ByteSource + '_ {
+    impl_async_for_sync!();
+}
+
+impl ByteSource for [u8] {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.get(start..start.checked_add(len)?))
+            .map(|b| b.to_vec())
+            .ok_or(io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl ByteSource for Vec<u8> {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        ByteSource::read_at(self.as_slice(), offset, len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl<T: ByteSource + ?Sized> ByteSource for &T {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_at(offset, len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+impl<T: ByteSource + ?Sized> ByteSource for Box<T> {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_at(offset, len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+impl<T: ByteSource + ?Sized> ByteSource for Arc<T> {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_at(offset, len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        (**self).as_bytes()
+    }
+}
+
+/// Any `Read + Seek` such as a `Cursor` or a stream of an object storage client,
+/// reads are serialized by a lock since they move the shared position.
+pub struct ReaderSource<R> {
+    reader: Mutex<R>,
+}
+
+impl<R: Read + Seek + Send> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderSource {
+            reader: Mutex::new(reader),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<R: Read + Seek + Send> ByteSource for ReaderSource<R> {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut reader = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let mut reader = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::End(0))
+    }
+}
+
+/// Memory mapped file, tiles are borrowed from the mapping instead of read by syscalls.
+#[cfg(feature = "mmap")]
+pub struct MmapSource {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapSource {
+    /// The file must not be truncated or modified while it is mapped, as for every mmap.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(MmapSource { map })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ByteSource for MmapSource {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        ByteSource::read_at(&self.map[..], offset, len)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        Some(&self.map)
+    }
+}
+
+/// Sync source such as a file read on tokio's blocking threads, so slides opened with
+/// `Eozin::open_async` don't block a worker of the runtime.
+#[cfg(feature = "tokio")]
+pub struct TokioSource<S> {
+    inner: Arc<S>,
+}
+
+#[cfg(feature = "tokio")]
+impl<S: ByteSource + 'static> TokioSource<S> {
+    pub fn new(source: S) -> Self {
+        TokioSource {
+            inner: Arc::new(source),
+        }
+    }
+
+    async fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&S) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&inner))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl TokioSource<std::fs::File> {
+    pub async fn open<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::task::spawn_blocking(move || std::fs::File::open(path))
+            .await
+            .map_err(io::Error::other)??;
+        Ok(TokioSource::new(file))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: ByteSource + 'static> AsyncByteSource for TokioSource<S> {
+    async fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        self.spawn(move |s| s.read_at(offset, len)).await
+    }
+
+    async fn size(&self) -> io::Result<u64> {
+        self.spawn(|s| s.size()).await
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        ByteSource::as_bytes(&*self.inner)
+    }
+}
+
+/// Positional read which leaves the file offset alone, so `&File` is shared among threads.
+#[cfg(any(unix, windows))]
+impl ByteSource for std::fs::File {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        read_exact_at(self, &mut buf, offset)?;
+        Ok(buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &std::fs::File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Bytes of `start..end`, a short source is reported as a broken file rather than IO error.
+pub(crate) async fn read_bytes<S: AsyncByteSource + ?Sized>(
+    source: &S,
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>, EozinError> {
+    let len = end
+        .checked_sub(start)
+        .and_then(|l| usize::try_from(l).ok())
+        .ok_or(EozinError::new("Invalid byte range"))?;
+    match source.read_at(start, len).await {
+        Ok(buf) => Ok(buf),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(EozinError::new("Buffer length is not match"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Calls `f` with bytes of `start..end`, which are borrowed from in-memory sources.
+pub(crate) async fn with_bytes<S: AsyncByteSource + ?Sized, T>(
+    source: &S,
+    start: u64,
+    end: u64,
+    f: impl FnOnce(&[u8]) -> T,
+) -> Result<T, EozinError> {
+    match source.as_bytes() {
+        Some(all) => usize::try_from(start)
+            .ok()
+            .zip(usize::try_from(end).ok())
+            .and_then(|(s, e)| all.get(s..e))
+            .map(f)
+            .ok_or(EozinError::new("Buffer length is not match")),
+        None => Ok(f(&read_bytes(source, start, end).await?)),
+    }
+}
+
+/// Future returned through `dyn` by [`Slide`](crate::std::Slide) and
+/// [`Vendor`](crate::std::Vendor) methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Source handed to [`Slide`](crate::std::Slide) and [`Vendor`](crate::std::Vendor)
+/// methods. Every read waits until the future awaiting the method reads the slide's
+/// own [`AsyncByteSource`], so vendors are written once for any source.
+pub struct SlideSource<'a> {
+    bytes: Option<&'a [u8]>,
+    size: u64,
+    reads: Mutex<Reads>,
+}
+
+/// Reads asked by the vendor and not answered yet, and answers not taken yet.
+/// Futures waiting on an answer are woken once it's in `done`.
+#[derive(Default)]
+struct Reads {
+    next: u64,
+    wanted: Vec<(u64, u64, usize)>,
+    done: HashMap<u64, io::Result<Vec<u8>>>,
+    wakers: Vec<Waker>,
+}
+
+impl<'a> SlideSource<'a> {
+    /// Source of `size` bytes, in-memory sources answer without waiting.
+    pub(crate) fn new(bytes: Option<&'a [u8]>, size: u64) -> Self {
+        SlideSource {
+            bytes,
+            size,
+            reads: Mutex::new(Reads::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Reads> {
+        self.reads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Polls `f` and answers the reads it waits on from `source`, until it's done.
+    /// A read of this source is pending until `run` answers it, then its waker is woken
+    /// and `f` polled again, so `f` must be driven by `run` and not polled alone.
+    pub(crate) async fn run<S: AsyncByteSource + ?Sized, T>(
+        &self,
+        source: &S,
+        mut f: BoxFuture<'_, T>,
+    ) -> T {
+        loop {
+            let done = std::future::poll_fn(|cx| match f.as_mut().poll(cx) {
+                Poll::Ready(v) => Poll::Ready(Some(v)),
+                Poll::Pending if !self.lock().wanted.is_empty() => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            })
+            .await;
+            if let Some(v) = done {
+                return v;
+            }
+            let wanted = std::mem::take(&mut self.lock().wanted);
+            for (ticket, offset, len) in wanted {
+                let r = source.read_at(offset, len).await;
+                self.lock().done.insert(ticket, r);
+            }
+            let wakers = std::mem::take(&mut self.lock().wakers);
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+impl AsyncByteSource for SlideSource<'_> {
+    fn read_at(&self, offset: u64, len: usize) -> impl Future<Output = io::Result<Vec<u8>>> {
+        let mut ticket = None;
+        std::future::poll_fn(move |cx| {
+            if let Some(all) = self.bytes {
+                return Poll::Ready(ByteSource::read_at(all, offset, len));
+            }
+            let mut reads = self.lock();
+            match ticket {
+                None => {
+                    let t = reads.next;
+                    reads.next += 1;
+                    reads.wanted.push((t, offset, len));
+                    ticket = Some(t);
+                }
+                Some(t) => {
+                    if let Some(r) = reads.done.remove(&t) {
+                        return Poll::Ready(r);
+                    }
+                }
+            }
+            if !reads.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                reads.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+
+    fn size(&self) -> impl Future<Output = io::Result<u64>> {
+        std::future::ready(Ok(self.size))
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        self.bytes
+    }
+}
+
+/// Wakes a thread parked by [`block_on`].
+struct Unpark(std::thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future on the calling thread, which sleeps until the future is woken.
+/// Futures reading a [`ByteSource`] are ready on their first poll.
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = pin!(f);
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests_source {
+    use super::*;
+
+    #[test]
+    fn test_read_bytes() {
+        let buf = vec![1u8, 2, 3, 4];
+        assert_eq!(block_on(read_bytes(&buf, 1, 3)).unwrap(), vec![2, 3]);
+        assert!(block_on(read_bytes(&buf, 3, 5)).is_err());
+        assert!(block_on(read_bytes(&buf, 3, 1)).is_err());
+        assert_eq!(ByteSource::size(&buf).unwrap(), 4);
+        let borrowed = block_on(with_bytes(&buf, 1, 3, |b| b.as_ptr())).unwrap();
+        assert_eq!(borrowed, buf[1..].as_ptr());
+    }
+
+    #[test]
+    fn test_reader_source() {
+        let r = ReaderSource::new(io::Cursor::new(vec![1u8, 2, 3, 4]));
+        assert_eq!(block_on(read_bytes(&r, 2, 4)).unwrap(), vec![3, 4]);
+        assert_eq!(block_on(read_bytes(&r, 0, 1)).unwrap(), vec![1]);
+        assert!(block_on(read_bytes(&r, 3, 5)).is_err());
+        assert_eq!(ByteSource::size(&r).unwrap(), 4);
+    }
+
+    /// Counts wakes of the task polling a slide read.
+    struct Count(Mutex<u32>);
+
+    impl Wake for Count {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn test_slide_source_wakes() {
+        let buf = vec![1u8, 2, 3, 4];
+        let s = SlideSource::new(None, 4);
+        let count = Arc::new(Count(Mutex::new(0)));
+        let waker = Waker::from(count.clone());
+        let run = pin!(s.run(&buf, Box::pin(read_bytes(&s, 1, 3))));
+        let r = run.poll(&mut Context::from_waker(&waker));
+        assert!(matches!(r, Poll::Ready(Ok(ref b)) if b == &[2, 3]));
+        assert_eq!(*count.0.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,265 @@
+//! Remote slides read by HTTP `Range` requests, fetched blocks are kept in a [`BlockCache`]
+//! shared by the native [`HttpSource`] and the `fetch` source of the WASM reader.
+#[cfg(feature = "http")]
+use super::{block_on, ByteSource};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+pub(crate) const BLOCK_SIZE: u64 = 64 * 1024;
+pub(crate) const CACHED_BLOCKS: usize = 512;
+
+/// Blocks of `block_size` bytes, least recently used ones are dropped beyond `capacity`.
+pub(crate) struct BlockCache {
+    block_size: u64,
+    capacity: usize,
+    blocks: Mutex<Blocks>,
+}
+
+/// Blocks stamped by their last use, the smallest stamp is dropped first.
+#[derive(Default)]
+struct Blocks {
+    map: HashMap<u64, (u64, Arc<Vec<u8>>)>,
+    order: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl Blocks {
+    fn stamp(&mut self, i: u64) -> u64 {
+        self.clock += 1;
+        self.order.insert(self.clock, i);
+        self.clock
+    }
+
+    fn get(&mut self, i: u64) -> Option<Arc<Vec<u8>>> {
+        let (stamp, b) = self.map.get(&i).cloned()?;
+        self.order.remove(&stamp);
+        let stamp = self.stamp(i);
+        self.map.insert(i, (stamp, b.clone()));
+        Some(b)
+    }
+
+    fn insert(&mut self, i: u64, b: Arc<Vec<u8>>, capacity: usize) {
+        let stamp = self.stamp(i);
+        if let Some((old, _)) = self.map.insert(i, (stamp, b)) {
+            self.order.remove(&old);
+        }
+        while self.map.len() > capacity {
+            let Some((_, j)) = self.order.pop_first() else {
+                break;
+            };
+            self.map.remove(&j);
+        }
+    }
+}
+
+impl BlockCache {
+    pub(crate) fn new(block_size: u64, capacity: usize) -> Self {
+        BlockCache {
+            block_size: block_size.max(1),
+            capacity,
+            blocks: Mutex::new(Blocks::default()),
+        }
+    }
+
+    /// `len` bytes at `offset` of a source of `size` bytes, every run of missing blocks
+    /// is fetched by a single `fetch(start, end)` request.
+    pub(crate) async fn read_at<F, Fut>(
+        &self,
+        size: u64,
+        offset: u64,
+        len: usize,
+        fetch: F,
+    ) -> io::Result<Vec<u8>>
+    where
+        F: Fn(u64, u64) -> Fut,
+        Fut: Future<Output = io::Result<Vec<u8>>>,
+    {
+        let end = offset
+            .checked_add(len as u64)
+            .filter(|e| *e <= size)
+            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let bs = self.block_size;
+        let first = offset / bs;
+        let mut blocks: Vec<Option<Arc<Vec<u8>>>> = {
+            let mut cached = self.lock();
+            (first..=(end - 1) / bs).map(|i| cached.get(i)).collect()
+        };
+        let mut i = 0;
+        while i < blocks.len() {
+            if blocks[i].is_some() {
+                i += 1;
+                continue;
+            }
+            let j = (i..blocks.len())
+                .find(|&j| blocks[j].is_some())
+                .unwrap_or(blocks.len());
+            let start = (first + i as u64) * bs;
+            let stop = ((first + j as u64) * bs).min(size);
+            let buf = fetch(start, stop).await?;
+            if buf.len() as u64 != stop - start {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut cached = self.lock();
+            for (k, chunk) in buf.chunks(bs as usize).enumerate() {
+                let b = Arc::new(chunk.to_vec());
+                cached.insert(first + (i + k) as u64, b.clone(), self.capacity);
+                blocks[i + k] = Some(b);
+            }
+            i = j;
+        }
+        let mut out = Vec::with_capacity(len);
+        for (k, b) in blocks.iter().enumerate() {
+            let b = b.as_deref().ok_or(io::ErrorKind::UnexpectedEof)?;
+            let block_start = (first + k as u64) * bs;
+            let s = offset.saturating_sub(block_start) as usize;
+            let e = ((end - block_start) as usize).min(b.len());
+            out.extend_from_slice(b.get(s..e).ok_or(io::ErrorKind::UnexpectedEof)?);
+        }
+        Ok(out)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Blocks> {
+        self.blocks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whole length from a `Content-Range` such as `bytes 0-0/1234`.
+pub(crate) fn content_range_size(v: &str) -> Option<u64> {
+    v.trim()
+        .strip_prefix("bytes ")?
+        .split('/')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+pub(crate) fn range_header(start: u64, end: u64) -> String {
+    format!("bytes={}-{}", start, end - 1)
+}
+
+/// Slide behind an HTTP server supporting `Range`, e.g. an object storage gateway.
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    url: String,
+    agent: ureq::Agent,
+    size: u64,
+    cache: BlockCache,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    /// Asks the first byte to learn the size, servers ignoring `Range` are refused.
+    pub fn open(url: &str) -> io::Result<Self> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into();
+        let resp = agent
+            .get(url)
+            .header("Range", range_header(0, 1))
+            .call()
+            .map_err(io::Error::other)?;
+        let size = match resp.status().as_u16() {
+            206 => resp
+                .headers()
+                .get("Content-Range")
+                .and_then(|v| v.to_str().ok())
+                .and_then(content_range_size),
+            _ => None,
+        }
+        .ok_or(io::Error::other(format!(
+            "{} doesn't answer range requests, status {}",
+            url,
+            resp.status()
+        )))?;
+        Ok(HttpSource {
+            url: url.to_string(),
+            agent,
+            size,
+            cache: BlockCache::new(BLOCK_SIZE, CACHED_BLOCKS),
+        })
+    }
+
+    /// Cache of `blocks` blocks of `block_size` bytes, 512 blocks of 64KiB by default.
+    pub fn with_cache(mut self, block_size: u64, blocks: usize) -> Self {
+        self.cache = BlockCache::new(block_size, blocks);
+        self
+    }
+
+    fn fetch(&self, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let mut resp = self
+            .agent
+            .get(&self.url)
+            .header("Range", range_header(start, end))
+            .call()
+            .map_err(io::Error::other)?;
+        if resp.status().as_u16() != 206 {
+            return Err(io::Error::other(format!(
+                "range request of {} is answered by status {}",
+                self.url,
+                resp.status()
+            )));
+        }
+        resp.body_mut()
+            .with_config()
+            // a body as long as the limit is refused
+            .limit(end - start + 1)
+            .read_to_vec()
+            .map_err(io::Error::other)
+    }
+}
+
+#[cfg(feature = "http")]
+impl ByteSource for HttpSource {
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        block_on(self.cache.read_at(self.size, offset, len, |s, e| {
+            std::future::ready(self.fetch(s, e))
+        }))
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests_http {
+    use super::*;
+    use crate::source::block_on;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_block_cache() {
+        let data: Vec<u8> = (0..100).collect();
+        let requests = RefCell::new(Vec::new());
+        let fetch = |s: u64, e: u64| {
+            requests.borrow_mut().push((s, e));
+            std::future::ready(Ok(data[s as usize..e as usize].to_vec()))
+        };
+        let cache = BlockCache::new(10, 3);
+        let read = |o, l| block_on(cache.read_at(100, o, l, fetch)).unwrap();
+        assert_eq!(read(15, 10), (15..25).collect::<Vec<u8>>());
+        assert_eq!(read(12, 5), (12..17).collect::<Vec<u8>>());
+        assert_eq!(read(95, 5), (95..100).collect::<Vec<u8>>());
+        assert_eq!(read(25, 10), (25..35).collect::<Vec<u8>>());
+        assert_eq!(*requests.borrow(), vec![(10, 30), (90, 100), (30, 40)]);
+        // block 1 was used least lately and made room for block 3
+        assert_eq!(read(90, 2), vec![90, 91]);
+        assert_eq!(read(10, 2), vec![10, 11]);
+        assert_eq!(requests.borrow().last(), Some(&(10, 20)));
+        assert!(block_on(cache.read_at(100, 95, 6, fetch)).is_err());
+    }
+
+    #[test]
+    fn test_content_range_size() {
+        assert_eq!(content_range_size("bytes 0-0/1234"), Some(1234));
+        assert_eq!(content_range_size("bytes */1234"), Some(1234));
+        assert_eq!(content_range_size("bytes 0-0/*"), None);
+        assert_eq!(range_header(10, 20), "bytes=10-19");
+    }
+}
@@ -1,257 +1,481 @@
-use self::ErrorType::*;
-use crate::tiff::{jpeg_in_tiff, property, tag::*, Data, ParseTiffError, Parser, Tiff};
+#[cfg(feature = "decode")]
+pub use crate::slide::RgbImage;
+pub use crate::slide::{
+    Aperio, AperioMetadata, CacheStats, CachedTiles, Confidence, Eozin, EozinError, GenericTiff,
+    Leica, LeicaImage, Ndpi, Philips, Registry, Slide, Tile, Vendor, Ventana,
+};
+use crate::source::block_on;
+#[cfg(feature = "http")]
+pub use crate::source::HttpSource;
+#[cfg(feature = "mmap")]
+pub use crate::source::MmapSource;
+#[cfg(feature = "tokio")]
+pub use crate::source::TokioSource;
+pub use crate::source::{AsyncByteSource, BoxFuture, ByteSource, ReaderSource, SlideSource};
 use std::{
-    collections::HashMap,
-    error, fmt,
     fs::File,
-    io,
-    io::{Read, Seek, SeekFrom},
+    io::{Read, Seek},
+    sync::Arc,
 };
 
-#[derive(Debug)]
-pub struct EozinError {
-    t: ErrorType,
-}
+impl Eozin {
+    pub fn open(path: &str) -> Result<Self, EozinError> {
+        Self::open_with(path, &Registry::default())
+    }
 
-#[derive(Debug)]
-enum ErrorType {
-    IoError(io::Error),
-    TiffError(ParseTiffError),
-    ParseWsiError(String),
-    MiscError(String),
-}
-impl fmt::Display for EozinError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self.t {
-            IoError(e) => write!(f, "IO Error {}", e).unwrap(),
-            TiffError(e) => write!(f, "Parse Tiff Error {}", e).unwrap(),
-            ParseWsiError(e) => write!(
-                f,
-                "Couldn't interpret given tiff file as Whole Slide Image {}",
-                e
-            )
-            .unwrap(),
-            MiscError(e) => write!(f, "Error {}", e).unwrap(),
-        }
-        Ok(())
+    pub fn open_with(path: &str, registry: &Registry) -> Result<Self, EozinError> {
+        Self::open_source_with(File::open(path)?, registry)
     }
-}
 
-impl From<io::Error> for EozinError {
-    fn from(err: io::Error) -> EozinError {
-        EozinError { t: IoError(err) }
+    /// Slide mapped into memory, tiles are copied from the mapping without `read` syscalls.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap(path: &str) -> Result<Self, EozinError> {
+        Self::open_source(MmapSource::open(path)?)
     }
-}
-impl From<ParseTiffError> for EozinError {
-    fn from(err: ParseTiffError) -> EozinError {
-        EozinError { t: TiffError(err) }
+
+    /// Slide behind an HTTP server answering `Range` requests, blocks read so far are cached.
+    #[cfg(feature = "http")]
+    pub fn open_url(url: &str) -> Result<Self, EozinError> {
+        Self::open_source(HttpSource::open(url)?)
     }
-}
-impl error::Error for EozinError {}
 
-#[non_exhaustive]
-pub enum Tile {
-    Jpeg(Vec<u8>),
-    Jp2k(Vec<u8>),
-}
+    /// Slide held in memory, e.g. an upload or an object fetched from a storage,
+    /// a `Vec<u8>` is taken without copying.
+    pub fn open_bytes<B: Into<Vec<u8>>>(bytes: B) -> Result<Self, EozinError> {
+        Self::open_source(bytes.into())
+    }
 
-impl Tile {
-    pub fn buffer(&self) -> &Vec<u8> {
-        match self {
-            Tile::Jpeg(v) => v,
-            Tile::Jp2k(v) => v,
-        }
+    /// Slide read from any `Read + Seek` such as a `Cursor`.
+    pub fn open_reader<R: Read + Seek + Send + 'static>(reader: R) -> Result<Self, EozinError> {
+        Self::open_source(ReaderSource::new(reader))
     }
-}
 
-pub struct Eozin {
-    format: Format,
-    pub level_count: u64,
-    pub dimensions: (u64, u64),
-    pub level_dimensions: Vec<(u64, u64)>,
-    pub level_tile_sizes: Vec<(u64, u64)>,
-}
+    /// Slide read from any [`ByteSource`], e.g. an in-memory buffer or a user-supplied one.
+    pub fn open_source<S: ByteSource + 'static>(source: S) -> Result<Self, EozinError> {
+        Self::open_source_with(source, &Registry::default())
+    }
 
-enum Format {
-    FormatAperio(Aperio),
+    pub fn open_source_with<S: ByteSource + 'static>(
+        source: S,
+        registry: &Registry,
+    ) -> Result<Self, EozinError> {
+        let source: Box<dyn ByteSource> = Box::new(source);
+        block_on(Self::open_async_with(source, registry))
+    }
 }
 
-pub struct Aperio {
-    data: Tiff,
-    file: File,
-    levels: Vec<AperioLevel>,
-    pub level_count: u64,
-    pub dimensions: (u64, u64),
-    pub level_dimensions: Vec<(u64, u64)>,
-    pub level_tile_sizes: Vec<(u64, u64)>,
+#[cfg(feature = "tokio")]
+impl Eozin<TokioSource<File>> {
+    /// Slide file read on tokio's blocking threads, async reads of it never block
+    /// a worker of the runtime.
+    pub async fn open_tokio(path: &str) -> Result<Self, EozinError> {
+        Self::open_async(TokioSource::open(path).await?).await
+    }
 }
 
-struct AperioLevel {
-    compression: u16,
-    jpeg_tables: Option<Vec<u8>>,
-    pub t: property::TiledIfd,
+impl<S: ByteSource> Eozin<S> {
+    pub fn read_tile(&self, lv: usize, x: usize, y: usize) -> Result<Arc<Tile>, EozinError> {
+        block_on(self.read_tile_async(lv, x, y))
+    }
+
+    /// Tile of the main image `image`, see [`Eozin::image_count`].
+    pub fn read_image_tile(
+        &self,
+        image: usize,
+        lv: usize,
+        x: usize,
+        y: usize,
+    ) -> Result<Arc<Tile>, EozinError> {
+        block_on(self.read_image_tile_async(image, lv, x, y))
+    }
+
+    /// RGBA pixels of `size` at `location` of level 0 read from `level`, as OpenSlide's
+    /// `read_region`, pixels outside the slide are transparent.
+    #[cfg(feature = "decode")]
+    pub fn read_region(
+        &self,
+        location: (i64, i64),
+        level: usize,
+        size: (u64, u64),
+    ) -> Result<RgbImage, EozinError> {
+        block_on(self.read_region_async(location, level, size))
+    }
+
+    /// RGBA thumbnail fitting in `max_size` with the aspect ratio of the slide, resized
+    /// from the smallest level or the thumbnail image which is large enough.
+    #[cfg(feature = "decode")]
+    pub fn get_thumbnail(&self, max_size: (u64, u64)) -> Result<RgbImage, EozinError> {
+        block_on(self.get_thumbnail_async(max_size))
+    }
+
+    pub fn read_associated_image(&self, name: &str) -> Result<Tile, EozinError> {
+        block_on(self.read_associated_image_async(name))
+    }
 }
 
-impl Eozin {
-    pub fn open(path: &str) -> Result<Self, EozinError> {
-        match Aperio::open(path) {
-            Ok(aperio) => Ok(Eozin {
-                level_count: aperio.level_count,
-                dimensions: aperio.dimensions.clone(),
-                level_dimensions: aperio.level_dimensions.clone(),
-                level_tile_sizes: aperio.level_tile_sizes.clone(),
-                format: Format::FormatAperio(aperio),
-            }),
-            Err(e) => Err(e),
+#[cfg(test)]
+mod tests_std {
+    use super::*;
+    use crate::tiff::tag;
+    use std::io::Cursor;
+
+    /// Little endian tiff of a 2x2 gray image in a single uncompressed strip.
+    fn gray_tiff() -> Vec<u8> {
+        let entries: [(u16, u16, u32); 8] = [
+            (256, 3, 2),
+            (257, 3, 2),
+            (258, 3, 8),
+            (259, 3, 1),
+            (273, 4, 110),
+            (277, 3, 1),
+            (278, 3, 2),
+            (279, 4, 4),
+        ];
+        let mut buf = b"II*\0".to_vec();
+        buf.extend(8u32.to_le_bytes());
+        buf.extend((entries.len() as u16).to_le_bytes());
+        for (tag, dt, v) in entries {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(dt.to_le_bytes());
+            buf.extend(1u32.to_le_bytes());
+            buf.extend(v.to_le_bytes());
         }
+        buf.extend(0u32.to_le_bytes());
+        buf.extend([1, 2, 3, 4]);
+        buf
     }
-    pub fn read_tile(&mut self, lv: usize, x: usize, y: usize) -> Result<Tile, EozinError> {
-        match &mut self.format {
-            Format::FormatAperio(ap) => ap.read_tile(lv, x, y),
+
+    /// Async source whose every read is pending once, as reads of a network.
+    struct Deferred(Vec<u8>);
+
+    impl AsyncByteSource for Deferred {
+        async fn read_at(&self, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+            let mut pending = true;
+            std::future::poll_fn(|cx| match std::mem::take(&mut pending) {
+                true => {
+                    cx.waker().wake_by_ref();
+                    std::task::Poll::Pending
+                }
+                false => std::task::Poll::Ready(()),
+            })
+            .await;
+            ByteSource::read_at(&self.0, offset, len)
+        }
+
+        async fn size(&self) -> std::io::Result<u64> {
+            Ok(self.0.len() as u64)
         }
     }
-}
 
-impl Aperio {
-    pub fn open(path: &str) -> Result<Self, EozinError> {
-        let mut file = File::open(path)?;
-        let data = decode_file(&mut file)?;
-        let mut levels = Vec::new();
-        let mut level_dimensions = Vec::new();
-        let mut level_tile_sizes = Vec::new();
-        let mut maybe_dimensions = None;
-        for ifd in data.iter() {
-            if ifd.contains_key(&TileOffsets) {
-                let maybe_cmp = ifd.get(&Compression).and_then(expect_short);
-                let jpeg_tables = ifd.get(&JPEGTables).and_then(u8vec).map(|v| v.clone());
-                let jpeg_tables = match jpeg_tables {
-                    Some(mut jptb) => {
-                        jpeg_in_tiff::set_app14_as_unknown(&mut jptb);
-                        Some(jptb)
-                    }
-                    None => None,
-                };
-                if let (Some(compression), Some(t)) = (maybe_cmp, property::tiled_ifd(ifd)) {
-                    level_dimensions.push((t.width, t.height));
-                    level_tile_sizes.push((t.tile_width, t.tile_height));
-                    maybe_dimensions = maybe_dimensions.or(Some((t.width, t.height)));
-                    let lv = AperioLevel {
-                        compression,
-                        jpeg_tables,
-                        t,
-                    };
-                    levels.push(lv);
-                }
-            }
+    #[test]
+    fn test_open_async() {
+        fn send<T: Send>(_: &T) {}
+        let e = block_on(Eozin::open_async(Deferred(gray_tiff()))).unwrap();
+        assert_eq!(e.level_dimensions, vec![(2, 2)]);
+        let tile = e.read_tile_async(0, 0, 0);
+        send(&tile);
+        assert_eq!(block_on(tile).unwrap().buffer(), &vec![1, 2, 3, 4]);
+        assert!(block_on(e.read_tile_async(0, 0, 1)).is_err());
+    }
+
+    #[test]
+    fn test_open_bytes() {
+        let tiff = gray_tiff();
+        for e in [
+            Eozin::open_bytes(tiff.as_slice()).unwrap(),
+            Eozin::open_reader(Cursor::new(tiff.clone())).unwrap(),
+        ] {
+            assert_eq!(e.vendor, "generic-tiff");
+            assert_eq!(e.level_dimensions, vec![(2, 2)]);
+            assert_eq!(e.read_tile(0, 0, 0).unwrap().buffer(), &vec![1, 2, 3, 4]);
         }
-        if let Some(dimensions) = maybe_dimensions {
-            Ok(Aperio {
-                data,
-                file,
-                levels,
-                dimensions,
-                level_count: level_dimensions.len() as u64,
-                level_dimensions,
-                level_tile_sizes,
-            })
-        } else {
-            Err(EozinError {
-                t: MiscError("Coundn't find any IFD on input fileh".to_string()),
-            })
+        assert!(Eozin::open_bytes(&tiff[..100]).is_err());
+    }
+
+    /// Vendor sure of every tiff which never opens one.
+    struct Failing;
+
+    impl Vendor for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+        fn detect(&self, _: &crate::tiff::Tiff) -> Confidence {
+            Confidence::High
         }
+        fn open<'a>(
+            &'a self,
+            _: &'a SlideSource<'_>,
+            _: crate::tiff::Tiff,
+        ) -> BoxFuture<'a, Result<Box<dyn Slide>, EozinError>> {
+            Box::pin(async { Err(EozinError::new("failing vendor")) })
+        }
+    }
+
+    #[test]
+    fn test_open_fallback() {
+        let mut registry = Registry::default();
+        registry.register(Failing);
+        let e = Eozin::open_source_with(gray_tiff(), &registry).unwrap();
+        assert_eq!(e.vendor, "generic-tiff");
+        let mut registry = Registry::new();
+        registry.register(Failing);
+        let err = Eozin::open_source_with(gray_tiff(), &registry)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("failing vendor"));
     }
 
-    pub fn read_tile(&mut self, lv: usize, x: usize, y: usize) -> Result<Tile, EozinError> {
-        let lv = self.levels.get(lv).ok_or(missing("level"))?;
-        let num_tiles_across = (lv.t.width + lv.t.tile_width - 1) / lv.t.tile_width;
-        let tile_id = (num_tiles_across as usize) * y + x;
-        let (addr, len) =
-            lv.t.offsets
-                .get(tile_id)
-                .and_then(|a| lv.t.byte_counts.get(tile_id).and_then(|l| Some((*a, *l))))
-                .ok_or(missing("selected tile is out of index"))?;
-        let buf = read_bytes(&mut self.file, addr as u64, (addr + len) as u64)?;
-        match (&lv.jpeg_tables, lv.compression) {
-            (Some(j_tb), 7) => {
-                let mut jpeg_tables: Vec<u8> = j_tb.clone();
-                let _ = jpeg_tables.split_off(jpeg_tables.len() - 2);
-                jpeg_tables.extend_from_slice(&buf[2..]);
-                Ok(Tile::Jpeg(jpeg_tables))
+    #[test]
+    fn test_generic_sub_ifds() {
+        use crate::tiff::{Data, IFD};
+        let strip = |w: u32, sub_ifds: Vec<IFD>| {
+            let mut ifd = IFD::from([
+                (tag::ImageWidth, Data::Long(w)),
+                (tag::ImageLength, Data::Long(w)),
+                (tag::Compression, Data::Short(1)),
+                (tag::StripOffsets, Data::Long(8)),
+                (tag::StripByteCounts, Data::Long(w * w)),
+            ]);
+            if !sub_ifds.is_empty() {
+                ifd.insert(tag::SubIFDs, Data::Ifds(sub_ifds));
             }
-            (_, 33003) => {
-                println!("JP2k YCbCr");
-                Ok(Tile::Jp2k(buf))
+            ifd
+        };
+        let tiff = vec![strip(64, vec![strip(16, vec![]), strip(32, vec![])])];
+        let g = GenericTiff::new(tiff).unwrap();
+        assert_eq!(g.level_dimensions, vec![(64, 64), (32, 32), (16, 16)]);
+    }
+
+    /// Fuzzed SVS whose thumbnail claims 10 x 1644167174 pixels in a 60 bytes strip.
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_huge_thumbnail() {
+        let svs = include_bytes!("../fuzz/corpus/open_bytes/svs_huge_thumbnail");
+        let e = Eozin::open_bytes(svs.to_vec()).unwrap();
+        assert_eq!(e.level_dimensions[0], (40, 33304));
+        assert!(e.read_associated_image("thumbnail").is_err());
+        assert!(e.get_thumbnail((32, 32)).is_err());
+    }
+
+    /// Level 0 of the seed SVS is 3x2 tiles of 16 pixels, each filled with its index.
+    #[test]
+    fn test_tile_out_of_index() {
+        let svs = include_bytes!("../fuzz/corpus/open_bytes/svs");
+        let e = Eozin::open_bytes(svs.to_vec()).unwrap();
+        assert_eq!(e.read_tile(0, 2, 1).unwrap().buffer()[0], 5);
+        assert!(e.read_tile(0, 3, 0).is_err());
+        assert!(e.read_tile(0, 0, 2).is_err());
+    }
+
+    /// Thumbnail of 16 bits samples in a strip of 8 bits ones, the pyramid has to be read.
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_broken_thumbnail() {
+        let mut svs = include_bytes!("../fuzz/corpus/open_bytes/svs").to_vec();
+        let bits = [0x02, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x08, 0x00];
+        let mut found = svs.windows(10).enumerate().filter(|(_, w)| *w == bits);
+        let (i, _) = found.nth(1).unwrap();
+        svs[i + 8] = 16;
+        let e = Eozin::open_bytes(svs).unwrap();
+        assert!(e.read_associated_image("thumbnail").is_err());
+        let img = e.get_thumbnail((20, 12)).unwrap();
+        assert_eq!((img.width, img.height), (20, 12));
+    }
+
+    /// Macro of two tiles filled with their index and a main image of two levels.
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_leica_macro() {
+        let scn = include_bytes!("../fuzz/corpus/open_bytes/scn");
+        let e = Eozin::open_bytes(scn.to_vec()).unwrap();
+        assert_eq!(e.vendor, "leica");
+        assert_eq!(e.level_dimensions, vec![(48, 32), (24, 16)]);
+        assert_eq!(e.associated_image_names(), vec!["macro".to_string()]);
+        let img = e.read_associated_image("macro").unwrap().decode().unwrap();
+        assert_eq!((img.width, img.height, img.channels), (32, 16, 4));
+        assert_eq!(&img.data[..4], &[0, 0, 0, 255]);
+        assert_eq!(&img.data[4 * 16..4 * 17], &[1, 1, 1, 255]);
+    }
+
+    /// Second main image of the collection is a level of 16x32 pixels in two tiles.
+    #[test]
+    fn test_leica_images() {
+        let scn = include_bytes!("../fuzz/corpus/open_bytes/scn");
+        let e = Eozin::open_bytes(scn.to_vec()).unwrap();
+        assert_eq!(e.image_count(), 2);
+        assert_eq!(e.image_level_dimensions(1), Some(vec![(16, 32)]));
+        assert_eq!(e.image_level_dimensions(2), None);
+        let tile = e.read_image_tile(1, 0, 0, 1).unwrap();
+        assert_eq!(tile.buffer()[0], 1);
+        assert_eq!(e.read_image_tile(0, 1, 0, 0).unwrap().buffer()[0], 0);
+        assert!(e.read_image_tile(1, 1, 0, 0).is_err());
+        assert!(e.read_image_tile(2, 0, 0, 0).is_err());
+    }
+
+    /// NDPI of a 64x16 gray JPEG of `restart_interval` MCUs of 8x8 between markers,
+    /// intervals of 4 MCUs are 32x8 tiles.
+    #[cfg(feature = "decode")]
+    fn ndpi(pixels: &[u8], restart_interval: u16) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg, 100);
+        encoder.set_restart_interval(restart_interval);
+        encoder
+            .encode(pixels, 64, 16, jpeg_encoder::ColorType::Luma)
+            .unwrap();
+        // entropy coded data follows the SOS segment, every RST starts the next tile
+        let sos = jpeg.windows(2).position(|m| m == [0xff, 0xda]).unwrap();
+        let mut mcu_starts =
+            vec![sos + 2 + u16::from_be_bytes([jpeg[sos + 2], jpeg[sos + 3]]) as usize];
+        for i in mcu_starts[0]..jpeg.len() - 1 {
+            if jpeg[i] == 0xff && (0xd0..=0xd7).contains(&jpeg[i + 1]) {
+                mcu_starts.push(i + 2);
             }
-            (_, 33005) => {
-                println!("JP2k RGB");
-                Ok(Tile::Jp2k(buf))
+        }
+        let n = mcu_starts.len() as u32;
+        assert_eq!(n, 16u32.div_ceil(restart_interval as u32));
+        let entries: [(u16, u16, u32, u32); 7] = [
+            (256, 4, 1, 64),
+            (257, 4, 1, 16),
+            (273, 4, 1, 8 + 2 + 7 * 12 + 4 + 4 * n),
+            (279, 4, 1, jpeg.len() as u32),
+            (65420, 4, 1, 1),
+            (65421, 11, 1, 20f32.to_bits()),
+            (65426, 4, n, 8 + 2 + 7 * 12 + 4),
+        ];
+        let mut buf = b"II*\0".to_vec();
+        buf.extend(8u32.to_le_bytes());
+        buf.extend((entries.len() as u16).to_le_bytes());
+        for (tag, dt, count, v) in entries {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(dt.to_le_bytes());
+            buf.extend(count.to_le_bytes());
+            buf.extend(v.to_le_bytes());
+        }
+        buf.extend(0u32.to_le_bytes());
+        buf.extend(mcu_starts.iter().flat_map(|&m| (m as u32).to_le_bytes()));
+        buf.extend(jpeg);
+        buf
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_ndpi() {
+        let pixels: Vec<u8> = (0..16u8)
+            .flat_map(|y| (0..64u8).map(move |x| x * 3 + y * 2))
+            .collect();
+        let e = Eozin::open_bytes(ndpi(&pixels, 4)).unwrap();
+        assert_eq!(e.vendor, "hamamatsu");
+        assert_eq!(e.level_dimensions, vec![(64, 16)]);
+        assert_eq!(e.level_tile_sizes, vec![(32, 8)]);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let tile = e.read_tile(0, x, y).unwrap().decode().unwrap();
+            assert_eq!((tile.width, tile.height, tile.channels), (32, 8, 1));
+            for (i, v) in tile.data.iter().enumerate() {
+                let (px, py) = (x * 32 + i % 32, y * 8 + i / 32);
+                assert!(v.abs_diff(pixels[py * 64 + px]) <= 4, "tile {:?}", (x, y));
             }
-            _ => Err(EozinError {
-                t: MiscError("Unknown compression".to_string()),
-            }),
         }
+        assert!(e.read_tile(0, 2, 0).is_err());
+        assert!(e.read_tile(0, 0, 2).is_err());
+
+        // 8 MCUs a row aren't intervals of 3, the level is a single tile
+        let e = Eozin::open_bytes(ndpi(&pixels, 3)).unwrap();
+        assert_eq!(e.level_tile_sizes, vec![(64, 16)]);
+        let tile = e.read_tile(0, 0, 0).unwrap().decode().unwrap();
+        assert_eq!((tile.width, tile.height), (64, 16));
+        assert!(tile
+            .data
+            .iter()
+            .zip(&pixels)
+            .all(|(a, b)| a.abs_diff(*b) <= 4));
+        assert!(e.read_tile(0, 0, 1).is_err());
     }
-}
 
-fn u8vec(d: &Data) -> Option<&Vec<u8>> {
-    match d {
-        Data::UndefinedVec(v) => Some(v),
-        Data::ByteVec(v) => Some(v),
-        _ => None,
+    #[cfg(feature = "decode")]
+    #[test]
+    fn test_cache() {
+        let e = Eozin::open_bytes(gray_tiff())
+            .unwrap()
+            .with_cache(1 << 20, CachedTiles::Both);
+        let a = e.read_region((0, 0), 0, (2, 2)).unwrap();
+        assert_eq!(e.cache_stats().misses, 2);
+        assert_eq!(e.read_region((0, 0), 0, (2, 2)).unwrap(), a);
+        let tile = e.read_tile(0, 0, 0).unwrap();
+        assert!(Arc::ptr_eq(&tile, &e.read_tile(0, 0, 0).unwrap()));
+        let stats = e.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (3, 2, 2));
+        assert_eq!(stats.bytes, 8);
     }
-}
 
-fn expect_short(d: &Data) -> Option<u16> {
-    match d {
-        Data::Short(b) => Some(*b),
-        _ => None,
+    /// Serves `body` answering `Range` requests, one request per connection.
+    #[cfg(feature = "http")]
+    fn serve_ranges(body: Vec<u8>) -> String {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/slide.tif", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut range = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    if let Some(r) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        let (s, e) = r.trim().split_once('-').unwrap();
+                        range = Some((s.parse::<usize>().unwrap(), e.parse::<usize>().unwrap()));
+                    }
+                    line.clear();
+                }
+                let (s, e) = range.unwrap();
+                let mut stream = &stream;
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    s,
+                    e,
+                    body.len(),
+                    e + 1 - s
+                )
+                .unwrap();
+                stream.write_all(&body[s..=e]).unwrap();
+            }
+        });
+        url
     }
-}
 
-fn decode_file(file: &mut File) -> Result<Tiff, EozinError> {
-    let buf = read_bytes(file, 0, 16)?;
-    let (p, ifd_offset) = Parser::header(&buf)?;
-    let size = p.size();
-    let mut next_ifd = Some(ifd_offset);
-    let mut directories: Tiff = Vec::new();
-    while let Some(ofs) = next_ifd {
-        let mut entries = HashMap::new();
-        let mut unloaded = Vec::new();
-        let buf = read_bytes(file, ofs, ofs + size.ifd_header)?;
-        let count = p.ifd_count(&buf)?;
-        let buf = read_bytes(
-            file,
-            ofs + size.ifd_header,
-            ofs + size.ifd_header + size.ifd_body(count),
-        )?;
-        next_ifd = p.ifd_body(&buf, &mut entries, &mut unloaded)?;
-        p.ifd_body(&buf, &mut entries, &mut unloaded)?;
-        for (tag, c, dt, addr, len) in unloaded.into_iter() {
-            let buf = read_bytes(file, addr, addr + len)?;
-            let data = p.entry(c, dt, &buf)?;
-            entries.insert(tag, data);
-        }
-        directories.push(entries);
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_open_url() {
+        let e = Eozin::open_url(&serve_ranges(gray_tiff())).unwrap();
+        assert_eq!(e.level_dimensions, vec![(2, 2)]);
+        assert_eq!(e.read_tile(0, 0, 0).unwrap().buffer(), &vec![1, 2, 3, 4]);
     }
-    Ok(directories)
-}
 
-fn read_bytes(file: &mut File, start: u64, end: u64) -> Result<Vec<u8>, EozinError> {
-    let len = (end - start) as usize;
-    let mut buffer = vec![0; len];
-    file.seek(SeekFrom::Start(start))?;
-    let l = file.read(&mut buffer)?;
-    if l == len {
-        Ok(buffer)
-    } else {
-        Err(EozinError {
-            t: MiscError("Buffer length is not match".to_string()),
-        })
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_open_tokio() {
+        fn send<T: Send>(_: &T) {}
+        let path = std::env::temp_dir().join(format!("eozin-tokio-{}.tif", std::process::id()));
+        std::fs::write(&path, gray_tiff()).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let e = rt
+            .block_on(Eozin::open_tokio(path.to_str().unwrap()))
+            .unwrap();
+        let tile = e.read_tile_async(0, 0, 0);
+        send(&tile);
+        assert_eq!(rt.block_on(tile).unwrap().buffer(), &vec![1, 2, 3, 4]);
+        std::fs::remove_file(path).unwrap();
     }
-}
 
-fn missing(s: &str) -> EozinError {
-    EozinError {
-        t: ParseWsiError(s.to_string()),
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_open_mmap() {
+        let path = std::env::temp_dir().join(format!("eozin-mmap-{}.tif", std::process::id()));
+        std::fs::write(&path, gray_tiff()).unwrap();
+        let e = Eozin::open_mmap(path.to_str().unwrap()).unwrap();
+        assert_eq!(e.read_tile(0, 0, 0).unwrap().buffer(), &vec![1, 2, 3, 4]);
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -1,8 +1,10 @@
+pub(crate) mod compression;
 pub(crate) mod data;
 pub(crate) mod jpeg_in_tiff;
 pub(crate) mod parser;
 pub(crate) mod property;
-pub(crate) mod tag;
+pub(crate) mod reader;
+pub mod tag;
 
-pub(crate) use data::{Data, Tiff};
-pub(crate) use parser::{ParseTiffError, Parser, Size};
+pub use data::{Data, Deferred, Tag, Tiff, IFD};
+pub(crate) use parser::{ParseTiffError, Parser};
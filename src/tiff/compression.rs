@@ -0,0 +1,126 @@
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
+#[cfg(feature = "native")]
+use super::ParseTiffError::{self, *};
+#[cfg(feature = "native")]
+use std::io::{self, Write};
+
+pub(crate) const NONE: u16 = 1;
+pub(crate) const LZW: u16 = 5;
+pub(crate) const JPEG: u16 = 7;
+pub(crate) const DEFLATE: u16 = 8;
+pub(crate) const ZSTD: u16 = 50000;
+pub(crate) const APERIO_JP2K_YCBCR: u16 = 33003;
+pub(crate) const APERIO_JP2K_RGB: u16 = 33005;
+
+/// Inflates lossless compressed tile or strip into its raw samples, output beyond
+/// `limit` bytes fails, so a small hostile tile can't inflate into gigabytes.
+#[cfg(feature = "native")]
+pub(crate) fn decompress(
+    compression: u16,
+    i: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, ParseTiffError> {
+    let mut out = Capped {
+        buf: Vec::new(),
+        limit,
+    };
+    match compression {
+        NONE => out
+            .write_all(i)
+            .map_err(|e| CompressionBroken(e.to_string()))?,
+        LZW => weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+            .into_stream(&mut out)
+            .decode_all(i)
+            .status
+            .map_err(|e| CompressionBroken(format!("lzw {}", e)))?,
+        DEFLATE => {
+            io::copy(&mut flate2::read::ZlibDecoder::new(i), &mut out)
+                .map_err(|e| CompressionBroken(format!("deflate {}", e)))?;
+        }
+        ZSTD => {
+            let mut i = i;
+            let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut i)
+                .map_err(|e| CompressionBroken(format!("zstd {}", e)))?;
+            io::copy(&mut decoder, &mut out)
+                .map_err(|e| CompressionBroken(format!("zstd {}", e)))?;
+        }
+        c => {
+            return Err(CompressionBroken(format!(
+                "compression {} is not supported",
+                c
+            )))
+        }
+    }
+    Ok(out.buf)
+}
+
+/// Sink refusing to grow beyond `limit` bytes.
+#[cfg(feature = "native")]
+struct Capped {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+#[cfg(feature = "native")]
+impl Write for Capped {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        if b.len() > self.limit - self.buf.len() {
+            return Err(io::Error::other(format!(
+                "output exceeds {} bytes",
+                self.limit
+            )));
+        }
+        self.buf.extend_from_slice(b);
+        Ok(b.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reverts horizontal differencing (Predictor = 2) of 8 bits samples in place.
+pub(crate) fn undo_horizontal_predictor(buf: &mut [u8], width: usize, samples: usize) {
+    let row_len = width.saturating_mul(samples);
+    if row_len == 0 {
+        return;
+    }
+    for row in buf.chunks_mut(row_len) {
+        for i in samples..row.len() {
+            row[i] = row[i].wrapping_add(row[i - samples]);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests_compression {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_decompress_lzw() {
+        let raw = b"eozin eozin eozin eozin".to_vec();
+        let encoded = weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+            .encode(&raw)
+            .unwrap();
+        assert_eq!(decompress(LZW, &encoded, raw.len()).unwrap(), raw);
+        assert!(decompress(LZW, &encoded, raw.len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_decompress_deflate() {
+        let raw = b"eozin eozin eozin eozin".to_vec();
+        let mut e = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        e.write_all(&raw).unwrap();
+        let encoded = e.finish().unwrap();
+        assert_eq!(decompress(DEFLATE, &encoded, raw.len()).unwrap(), raw);
+        assert!(decompress(DEFLATE, &encoded, 8).is_err());
+    }
+
+    #[test]
+    fn test_undo_horizontal_predictor() {
+        let mut buf = vec![10, 20, 1, 2, 1, 2, 5, 5, 0, 0, 1, 1];
+        undo_horizontal_predictor(&mut buf, 3, 2);
+        assert_eq!(buf, vec![10, 20, 11, 22, 12, 24, 5, 5, 5, 5, 6, 6]);
+    }
+}
@@ -1,14 +1,20 @@
+use super::parser::Bytes;
 use std::collections::HashMap;
 
-pub(crate) type Tiff = Vec<IFD>;
-pub(crate) type Tag = u16;
-pub(crate) type IFD = HashMap<Tag, Data>;
+/// Every IFD of a tiff file in the order of the next IFD chain, directories such as
+/// SubIFDs or EXIF are nested in the entry pointing to them as [`Data::Ifds`].
+pub type Tiff = Vec<IFD>;
+pub type Tag = u16;
+#[allow(clippy::upper_case_acronyms)]
+pub type IFD = HashMap<Tag, Data>;
 
+#[allow(dead_code)]
 pub(crate) enum Entry {
     DataEntry(Data),
     OffsetEntry(DataOffset),
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct DataOffset {
     pub data_type: DataType,
@@ -16,21 +22,29 @@ pub(crate) struct DataOffset {
     pub offset: u64,
 }
 
+/// Value of a tiff entry, a count of one is decoded as a scalar.
 #[allow(dead_code)]
-#[derive(Debug, PartialEq)]
-pub(crate) enum Data {
+#[derive(Debug, PartialEq, Clone)]
+pub enum Data {
     Byte(u8),
     Ascii(String),
     Short(u16),
     Long(u32),
-    Rational { numer: u32, denom: u32 }, // 分子, 分母
+    Rational {
+        numer: u32,
+        denom: u32,
+    }, // 分子, 分母
     SByte(i8),
     Undefined(u8),
     SShort(i16),
     SLong(i32),
-    SRational { numer: i32, denom: i32 }, // 分子, 分母
+    SRational {
+        numer: i32,
+        denom: i32,
+    }, // 分子, 分母
     Float(f32),
     Double(f64),
+    Ifd(u32),
 
     Long8(u64),
     SLong8(i64),
@@ -47,14 +61,46 @@ pub(crate) enum Data {
     SRationalVec(Vec<(i32, i32)>),
     FloatVec(Vec<f32>),
     DoubleVec(Vec<f64>),
+    IfdVec(Vec<u32>),
 
     Long8Vec(Vec<u64>),
     SLong8Vec(Vec<i64>),
     Ifd8Vec(Vec<u64>),
+
+    /// Large TileOffsets or TileByteCounts, read when a tile of the level is.
+    Deferred(Deferred),
+    /// Directories a pointer tag such as SubIFDs or ExifIFD refers to, each followed
+    /// by the IFDs chained after it.
+    Ifds(Vec<IFD>),
+}
+
+/// Array entry left in the file at `offset`, opening a slide of many tiles reads
+/// a few small ranges instead of every offset.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Deferred {
+    pub(crate) bytes: Bytes,
+    pub(crate) data_type: DataType,
+    pub(crate) count: u64,
+    pub(crate) offset: u64,
+}
+
+impl Deferred {
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.data_type.size().saturating_mul(self.count)
+    }
 }
 
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum DataType {
     BYTE, // uint8
@@ -69,6 +115,7 @@ pub(crate) enum DataType {
     SRATIONAL,
     FLOAT,
     DOUBLE,
+    IFD,
     LONG8,
     SLONG8,
     IFD8,
@@ -91,6 +138,7 @@ impl DataType {
             10 => Some(SRATIONAL),
             11 => Some(FLOAT),
             12 => Some(DOUBLE),
+            13 => Some(IFD),
             16 => Some(LONG8),
             17 => Some(SLONG8),
             18 => Some(IFD8),
@@ -113,6 +161,7 @@ impl DataType {
             SRATIONAL => 10,
             FLOAT => 11,
             DOUBLE => 12,
+            IFD => 13,
             LONG8 => 16,
             SLONG8 => 17,
             IFD8 => 18,
@@ -124,7 +173,7 @@ impl DataType {
         match self {
             BYTE | ASCII | SBYTE | UNDEFINED => 1,
             SHORT | SSHORT => 2,
-            LONG | SLONG | FLOAT => 4,
+            LONG | SLONG | FLOAT | IFD => 4,
             RATIONAL | SRATIONAL | DOUBLE | LONG8 | SLONG8 | IFD8 => 8,
         }
     }
@@ -1,3 +1,4 @@
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
 const APP14_SEGMENT_TRANSFORM_UNKNOWN: [u8; 16] = [
     0xff, 0xee, 0x00, 0x0e, 0x41, 0x64, 0x6f, 0x62, 0x65, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
@@ -14,7 +15,9 @@ pub(crate) fn set_app14_as_unknown(jpeg_tables: &mut Vec<u8>) {
     }
     match (app14_ofs, dht_ofs) {
         (Some(i), _) => {
-            jpeg_tables[i + 16] = 0x00;
+            if let Some(transform) = jpeg_tables.get_mut(i + 15) {
+                *transform = 0x00;
+            }
         }
         (None, Some(sos_ofs)) => {
             jpeg_tables.splice(
@@ -25,3 +28,174 @@ pub(crate) fn set_app14_as_unknown(jpeg_tables: &mut Vec<u8>) {
         _ => {}
     }
 }
+
+/// Transform flag of the Adobe APP14 segment, 0 is unknown (RGB or CMYK) and 1 is YCbCr.
+#[cfg_attr(not(feature = "decode"), allow(dead_code))]
+pub(crate) fn adobe_transform(jpeg: &[u8]) -> Option<u8> {
+    let mut i = 2;
+    while i + 4 <= jpeg.len() && jpeg[i] == 0xff {
+        let marker = jpeg[i + 1];
+        let len = ((jpeg[i + 2] as usize) << 8) + jpeg[i + 3] as usize;
+        match marker {
+            0xee => {
+                let seg = jpeg.get(i + 4..i + 2 + len)?;
+                if seg.len() >= 12 && seg.starts_with(b"Adobe") {
+                    return Some(seg[11]);
+                }
+            }
+            0xda => return None,
+            _ => {}
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+/// Prepends JPEGTables, without its EOI, to an abbreviated strip or tile without its SOI.
+pub(crate) fn with_tables(jpeg_tables: Option<&[u8]>, buf: &[u8]) -> Vec<u8> {
+    match jpeg_tables {
+        Some(tb) if tb.len() >= 2 && buf.len() >= 2 => {
+            let mut jpeg = tb[..tb.len() - 2].to_vec();
+            jpeg.extend_from_slice(&buf[2..]);
+            jpeg
+        }
+        _ => buf.to_vec(),
+    }
+}
+
+struct Frame {
+    sof: usize,
+    sos: usize,
+    scan: usize,
+    width: u64,
+    height: u64,
+    mcu_width: u64,
+    mcu_height: u64,
+    restart: bool,
+}
+
+fn frame(jpeg: &[u8]) -> Option<Frame> {
+    let (mut sof, mut restart) = (None, false);
+    let mut i = 2;
+    while i + 4 <= jpeg.len() {
+        if jpeg[i] != 0xff {
+            return None;
+        }
+        let marker = jpeg[i + 1];
+        let len = ((jpeg[i + 2] as usize) << 8) + jpeg[i + 3] as usize;
+        let seg = jpeg.get(i + 4..i + 2 + len)?;
+        match marker {
+            0xc0..=0xc2 => {
+                let height = ((*seg.get(1)? as u64) << 8) + *seg.get(2)? as u64;
+                let width = ((*seg.get(3)? as u64) << 8) + *seg.get(4)? as u64;
+                let n = *seg.get(5)? as usize;
+                let (h, v) = (0..n).try_fold((1, 1), |(h, v), c| {
+                    let hv = *seg.get(7 + 3 * c)?;
+                    Some((h.max(hv >> 4), v.max(hv & 0x0f)))
+                })?;
+                sof = Some((i, width, height, 8 * h as u64, 8 * v as u64));
+            }
+            0xdd => restart = true,
+            0xda => {
+                let (sof, width, height, mcu_width, mcu_height) = sof?;
+                return Some(Frame {
+                    sof,
+                    sos: i,
+                    scan: i + 2 + len,
+                    width,
+                    height,
+                    mcu_width,
+                    mcu_height,
+                    restart,
+                });
+            }
+            _ => {}
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+/// Joins JPEG strips into one JPEG of `height` rows, every strip becomes a restart interval.
+///
+/// Returns `None` unless strips are MCU aligned and have no restart interval of their own.
+pub(crate) fn join_strips(
+    jpeg_tables: Option<&[u8]>,
+    strips: &[Vec<u8>],
+    height: u64,
+) -> Option<Vec<u8>> {
+    let first = with_tables(jpeg_tables, strips.first()?);
+    if strips.len() == 1 {
+        return Some(first);
+    }
+    let f = frame(&first)?;
+    if f.restart || f.height == 0 || f.height % f.mcu_height != 0 {
+        return None;
+    }
+    let interval = f.width.div_ceil(f.mcu_width) * (f.height / f.mcu_height);
+    let interval = u16::try_from(interval).ok()?;
+    let height = u16::try_from(height).ok()?;
+    let mut jpeg = first[..f.sos].to_vec();
+    jpeg[f.sof + 5..f.sof + 7].copy_from_slice(&height.to_be_bytes());
+    jpeg.extend_from_slice(&[0xff, 0xdd, 0x00, 0x04]);
+    jpeg.extend_from_slice(&interval.to_be_bytes());
+    jpeg.extend_from_slice(&first[f.sos..f.scan]);
+    for (n, strip) in strips.iter().enumerate() {
+        let entropy = match n {
+            0 => &first[f.scan..],
+            _ => &strip[frame(strip)?.scan..],
+        };
+        let entropy = match entropy {
+            [rest @ .., 0xff, 0xd9] => rest,
+            _ => entropy,
+        };
+        if n > 0 {
+            jpeg.extend_from_slice(&[0xff, 0xd0 + ((n - 1) % 8) as u8]);
+        }
+        jpeg.extend_from_slice(entropy);
+    }
+    jpeg.extend_from_slice(&[0xff, 0xd9]);
+    Some(jpeg)
+}
+
+#[cfg(test)]
+mod tests_jpeg_in_tiff {
+    use super::*;
+
+    fn strip(entropy: &[u8]) -> Vec<u8> {
+        let mut s = vec![
+            0xff, 0xd8, // SOI
+            0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x20, 0x03, // SOF0, 32x16 px
+            0x01, 0x22, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01, // YCbCr 4:2:0
+            0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, // SOS
+        ];
+        s.extend_from_slice(entropy);
+        s.extend_from_slice(&[0xff, 0xd9]);
+        s
+    }
+
+    #[test]
+    fn test_set_app14_as_unknown() {
+        let mut jpeg = strip(&[0x12]);
+        assert_eq!(adobe_transform(&jpeg), None);
+        jpeg.splice(2..2, APP14_SEGMENT_TRANSFORM_UNKNOWN.iter().copied());
+        jpeg[17] = 0x01;
+        assert_eq!(adobe_transform(&jpeg), Some(1));
+        set_app14_as_unknown(&mut jpeg);
+        assert_eq!(adobe_transform(&jpeg), Some(0));
+        assert_eq!(&jpeg[18..20], &[0xff, 0xc0]);
+    }
+
+    #[test]
+    fn test_join_strips() {
+        let strips = vec![strip(&[0x12]), strip(&[0x34]), strip(&[0x56])];
+        let jpeg = join_strips(None, &strips, 40).unwrap();
+        assert_eq!(&jpeg[7..9], &[0x00, 0x28]);
+        assert_eq!(&jpeg[21..27], &[0xff, 0xdd, 0x00, 0x04, 0x00, 0x02]);
+        assert_eq!(
+            &jpeg[37..],
+            &[0x12, 0xff, 0xd0, 0x34, 0xff, 0xd1, 0x56, 0xff, 0xd9]
+        );
+        assert_eq!(join_strips(None, &strips[..1], 16), Some(strips[0].clone()));
+    }
+}
@@ -11,11 +11,21 @@ type Offset = u64;
 type Address = u64;
 type Len = u64;
 
+/// Entries of an IFD beyond this are taken as a broken count, as libtiff does.
+pub(crate) const MAX_IFD_ENTRIES: u64 = 65535;
+/// Values of an entry beyond this are taken as a broken count rather than allocated,
+/// tile offsets of the largest slides are a few MB.
+pub(crate) const MAX_ENTRY_LEN: u64 = 256 << 20;
+
 #[derive(Debug)]
 pub(crate) enum ParseTiffError {
     TiffHeaderBroken(String),
     TiffEntryBroken(String),
     BufferLengthNotEnough(u64),
+    OffsetOutOfFile(u64),
+    IfdLoop(u64),
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    CompressionBroken(String),
 }
 
 impl fmt::Display for ParseTiffError {
@@ -29,6 +39,9 @@ impl fmt::Display for ParseTiffError {
                 s
             )
             .unwrap(),
+            OffsetOutOfFile(s) => write!(f, "Offset {} of tiff is out of the file", s).unwrap(),
+            IfdLoop(s) => write!(f, "IFD at {} is linked again, IFDs make a loop", s).unwrap(),
+            CompressionBroken(s) => write!(f, "Decompressing tiff data is failed: {}", s).unwrap(),
         }
         Ok(())
     }
@@ -56,12 +69,14 @@ pub(crate) struct Size {
 
 impl Size {
     pub(crate) fn ifd_body(&self, c: u64) -> u64 {
-        c * self.ent_size + self.next_ifd_ofs_size
+        c.saturating_mul(self.ent_size)
+            .saturating_add(self.next_ifd_ofs_size)
     }
 }
 impl Parser {
     pub(crate) fn header(i: &[u8]) -> Result<(Self, Offset), ParseTiffError> {
-        let bp = match Moto.u16(&i[0..2]).unwrap() {
+        let at = |start: usize| i.get(start..).unwrap_or_default();
+        let bp = match Moto.u16(i).ok_or(BufferLengthNotEnough(2))? {
             18761 => {
                 // Little endian(Intel) 18761 == 0x49, 0x49
                 Intel
@@ -72,16 +87,15 @@ impl Parser {
             }
             dbg => return Err(TiffHeaderBroken(format!("Unknown endian: {:?}", dbg))),
         };
-        match bp.u16(&i[2..4]).unwrap() {
+        match bp.u16(at(2)).ok_or(BufferLengthNotEnough(4))? {
             42 => {
-                let next_ifd = bp.u32(&i[4..8]).unwrap() as u64;
+                let next_ifd = bp.u32(at(4)).ok_or(BufferLengthNotEnough(8))? as u64;
                 Ok((Classic(bp), next_ifd))
             }
             43 => {
-                let i = &i[4..16];
-                let (always_8, always_0) = (bp.u16(&i[..2]).unwrap(), bp.u16(&i[2..4]).unwrap());
-                if always_8 == 8 && always_0 == 0 {
-                    let next_ifd = bp.u64(&i[4..12]).unwrap();
+                let (always_8, always_0) = (bp.u16(at(4)), bp.u16(at(6)));
+                if always_8 == Some(8) && always_0 == Some(0) {
+                    let next_ifd = bp.u64(at(8)).ok_or(BufferLengthNotEnough(16))?;
                     Ok((Big(bp), next_ifd))
                 } else {
                     Err(TiffHeaderBroken("Unknown tff version".to_string()))
@@ -110,10 +124,30 @@ impl Parser {
         match *self {
             Classic(p) => p.u16(i).map(|x| x as u64).ok_or(BufferLengthNotEnough(2)),
 
-            Big(p) => p.u64(i).ok_or(BufferLengthNotEnough(8)),
+            Big(p) => p
+                .u64(i)
+                .ok_or(BufferLengthNotEnough(8))
+                .and_then(|c| match c {
+                    c if c > MAX_IFD_ENTRIES => Err(TiffEntryBroken(format!(
+                        "{} entries in an IFD are too many",
+                        c
+                    ))),
+                    c => Ok(c),
+                }),
         }
     }
 
+    /// Bytes of `count` values of `dt`, absurd counts are refused before anything is read.
+    fn entry_len(dt: &DataType, count: u64) -> Result<Len, ParseTiffError> {
+        dt.size()
+            .checked_mul(count)
+            .filter(|len| *len <= MAX_ENTRY_LEN)
+            .ok_or(TiffEntryBroken(format!(
+                "{} values of {:?} are too many",
+                count, dt
+            )))
+    }
+
     pub(crate) fn ifd_body(
         &self,
         i: &[u8],
@@ -122,11 +156,12 @@ impl Parser {
     ) -> Result<Option<Address>, ParseTiffError> {
         match *self {
             Classic(p) => {
-                for j in i.chunks(12) {
+                let idx = (i.len() / 12) * 12;
+                for j in i[..idx].chunks_exact(12) {
                     let tag = p.u16(&j[..2]).unwrap();
                     if let Some(dt) = p.u16(&j[2..4]).and_then(DataType::from_u16) {
                         let count = p.u32(&j[4..8]).unwrap() as u64;
-                        let len = dt.size() * count;
+                        let len = Self::entry_len(&dt, count)?;
                         if len <= 4 {
                             let data = self.entry(count, dt, &j[8..12])?;
                             entries.insert(tag, data);
@@ -138,16 +173,16 @@ impl Parser {
                         continue;
                     }
                 }
-                let idx = (i.len() / 12) * 12;
-                Ok(p.u32(&i[idx..idx + 4])
+                Ok(p.u32(&i[idx..])
                     .and_then(|x| if x == 0 { None } else { Some(x as u64) }))
             }
             Big(p) => {
-                for j in i.chunks(20) {
+                let idx = (i.len() / 20) * 20;
+                for j in i[..idx].chunks_exact(20) {
                     let tag = p.u16(&j[..2]).unwrap();
                     if let Some(dt) = p.u16(&j[2..4]).and_then(DataType::from_u16) {
                         let count = p.u64(&j[4..12]).unwrap();
-                        let len = dt.size() * count;
+                        let len = Self::entry_len(&dt, count)?;
                         if len <= 8 {
                             let data = self.entry(count, dt, &j[12..20])?;
                             entries.insert(tag, data);
@@ -160,17 +195,58 @@ impl Parser {
                         continue;
                     }
                 }
-                let idx = (i.len() / 20) * 20;
-                Ok(p.u64(&i[idx..idx + 8])
+                Ok(p.u64(&i[idx..])
                     .and_then(|x| if x == 0 { None } else { Some(x) }))
             }
         }
     }
-    pub(crate) fn entry(&self, c: u64, dt: DataType, i: &[u8]) -> Result<Data, ParseTiffError> {
+    /// NDPI files beyond 4GB keep classic 12 bytes entries, but the next IFD offset is 8 bytes
+    /// and followed by the high 32 bits of every entry's value/offset field.
+    /// `i` holds the entries only and `ext` holds the next IFD offset and the high words.
+    pub(crate) fn ndpi_ifd_body(
+        &self,
+        i: &[u8],
+        ext: &[u8],
+        entries: &mut HashMap<Tag, Data>,
+        unloaded: &mut Vec<(Tag, Count, DataType, Address, Len)>,
+    ) -> Result<Option<Address>, ParseTiffError> {
         let p = match *self {
             Classic(p) => p,
-            Big(p) => p,
+            Big(_) => return Err(TiffHeaderBroken("NDPI must be a classic tiff".to_string())),
         };
+        let next_ifd = p.u64(ext).ok_or(BufferLengthNotEnough(8))?;
+        for (k, j) in i.chunks_exact(12).enumerate() {
+            let tag = p.u16(&j[..2]).unwrap();
+            let hi = p
+                .u32(&ext[(8 + 4 * k).min(ext.len())..])
+                .ok_or(BufferLengthNotEnough(8 + 4 * (k as u64 + 1)))? as u64;
+            if let Some(dt) = p.u16(&j[2..4]).and_then(DataType::from_u16) {
+                let count = p.u32(&j[4..8]).unwrap() as u64;
+                let len = Self::entry_len(&dt, count)?;
+                let low = p.u32(&j[8..12]).unwrap() as u64;
+                if len <= 4 {
+                    let data = match (&dt, count, hi) {
+                        (LONG, 1, hi) if hi > 0 => Long8((hi << 32) + low),
+                        _ => self.entry(count, dt, &j[8..12])?,
+                    };
+                    entries.insert(tag, data);
+                } else {
+                    unloaded.push((tag, count, dt, (hi << 32) + low, len));
+                }
+            }
+        }
+        Ok(if next_ifd == 0 { None } else { Some(next_ifd) })
+    }
+
+    pub(crate) fn bytes(&self) -> Bytes {
+        match *self {
+            Classic(p) => p,
+            Big(p) => p,
+        }
+    }
+
+    pub(crate) fn entry(&self, c: u64, dt: DataType, i: &[u8]) -> Result<Data, ParseTiffError> {
+        let p = self.bytes();
         fn e(m: Option<Data>, s: &str) -> Result<Data, ParseTiffError> {
             m.ok_or(TiffEntryBroken(s.to_string()))
         }
@@ -185,6 +261,8 @@ impl Parser {
             (LONG, n) => e(p.u32_vec(n, i).map(LongVec), "long"),
             (LONG8, 1) => e(p.u64(i).map(Long8), "long8"),
             (LONG8, n) => e(p.u64_vec(n, i).map(Long8Vec), "long8"),
+            (IFD, 1) => e(p.u32(i).map(Ifd), "ifd"),
+            (IFD, n) => e(p.u32_vec(n, i).map(IfdVec), "ifd"),
             (IFD8, 1) => e(p.u64(i).map(Ifd8), "ifd8"),
             (IFD8, n) => e(p.u64_vec(n, i).map(Ifd8Vec), "ifd8"),
             (ASCII, n) => e(p.ascii(n, i).map(Ascii), "ascii"),
@@ -193,44 +271,72 @@ impl Parser {
                 "rational",
             ),
             (RATIONAL, c) => e(p.rational_vec(c, i).map(RationalVec), "rational"),
-            (dt, _) => e(None, &format!("data-type {:?} is not supported", dt)),
+            (SBYTE, 1) => e(p.u8(i).map(|x| SByte(x as i8)), "sbyte"),
+            (SBYTE, n) => e(
+                p.u8_vec(n, i)
+                    .map(|v| SByteVec(v.into_iter().map(|x| x as i8).collect())),
+                "sbyte",
+            ),
+            (SSHORT, 1) => e(p.u16(i).map(|x| SShort(x as i16)), "sshort"),
+            (SSHORT, n) => e(
+                p.u16_vec(n, i)
+                    .map(|v| SShortVec(v.into_iter().map(|x| x as i16).collect())),
+                "sshort",
+            ),
+            (SLONG, 1) => e(p.u32(i).map(|x| SLong(x as i32)), "slong"),
+            (SLONG, n) => e(
+                p.u32_vec(n, i)
+                    .map(|v| SLongVec(v.into_iter().map(|x| x as i32).collect())),
+                "slong",
+            ),
+            (SLONG8, 1) => e(p.u64(i).map(|x| SLong8(x as i64)), "slong8"),
+            (SLONG8, n) => e(
+                p.u64_vec(n, i)
+                    .map(|v| SLong8Vec(v.into_iter().map(|x| x as i64).collect())),
+                "slong8",
+            ),
+            (SRATIONAL, 1) => e(
+                p.rational(i).map(|(n, d)| SRational {
+                    numer: n as i32,
+                    denom: d as i32,
+                }),
+                "srational",
+            ),
+            (SRATIONAL, c) => e(
+                p.rational_vec(c, i).map(|v| {
+                    SRationalVec(v.into_iter().map(|(n, d)| (n as i32, d as i32)).collect())
+                }),
+                "srational",
+            ),
+            (FLOAT, 1) => e(p.u32(i).map(|x| Float(f32::from_bits(x))), "float"),
+            (FLOAT, n) => e(
+                p.u32_vec(n, i)
+                    .map(|v| FloatVec(v.into_iter().map(f32::from_bits).collect())),
+                "float",
+            ),
+            (DOUBLE, 1) => e(p.u64(i).map(|x| Double(f64::from_bits(x))), "double"),
+            (DOUBLE, n) => e(
+                p.u64_vec(n, i)
+                    .map(|v| DoubleVec(v.into_iter().map(f64::from_bits).collect())),
+                "double",
+            ),
         }
     }
 }
 
-#[cfg(test)]
-mod tests_tiff_parser {
-    use super::*;
-
-    #[test]
-    fn test_header_classic_intel() {
-        let buf = [0x49, 0x49, 0x2A, 0x00, 0x09, 0x00, 0x00, 0x00];
-        let result = Parser::header(&buf);
-        match result {
-            Ok((parser, next_ifd)) => {
-                assert_eq!(parser, Classic(Intel));
-                assert_eq!(next_ifd, 9);
-            }
-            _ => {
-                assert!(false, "Failed to parse header");
-            }
-        }
-    }
+/// Bytes of the first `n` values of `size` bytes, `None` when `i` is shorter.
+fn values(i: &[u8], n: u64, size: usize) -> Option<&[u8]> {
+    i.get(..usize::try_from(n).ok()?.checked_mul(size)?)
 }
-#[allow(dead_code)]
+
 impl Bytes {
     fn ascii(&self, n: u64, i: &[u8]) -> Option<String> {
-        let n = n as usize;
-        if i.len() < n {
-            return None;
-        } else {
-            Some(i[0..n].iter().fold(String::new(), |mut acc, x| {
-                if let Some(c) = std::char::from_u32(*x as u32) {
-                    acc.push(c);
-                }
-                acc
-            }))
-        }
+        Some(values(i, n, 1)?.iter().fold(String::new(), |mut acc, x| {
+            if let Some(c) = std::char::from_u32(*x as u32) {
+                acc.push(c);
+            }
+            acc
+        }))
     }
 
     fn rational(&self, i: &[u8]) -> Option<(u32, u32)> {
@@ -245,18 +351,14 @@ impl Bytes {
     }
 
     fn rational_vec(&self, n: u64, i: &[u8]) -> Option<Vec<(u32, u32)>> {
-        let n = n as usize;
-        if i.len() < n * 2 {
-            return None;
-        }
-        Some(i[0..8 * n].chunks(8).fold(Vec::new(), |mut acc, x| {
-            acc.push(self.rational(x).unwrap());
-            acc
-        }))
+        values(i, n, 8)?
+            .chunks_exact(8)
+            .map(|x| self.rational(x))
+            .collect()
     }
 
     fn u8(&self, i: &[u8]) -> Option<u8> {
-        if i.len() < 1 {
+        if i.is_empty() {
             None
         } else {
             Some(i[0])
@@ -264,12 +366,7 @@ impl Bytes {
     }
 
     fn u8_vec(&self, n: u64, i: &[u8]) -> Option<Vec<u8>> {
-        let n = n as usize;
-        if i.len() < n {
-            return None;
-        } else {
-            Some(i[0..n].to_vec())
-        }
+        values(i, n, 1).map(|v| v.to_vec())
     }
 
     fn u16(&self, i: &[u8]) -> Option<u16> {
@@ -283,14 +380,10 @@ impl Bytes {
     }
 
     fn u16_vec(&self, n: u64, i: &[u8]) -> Option<Vec<u16>> {
-        let n = n as usize;
-        if i.len() < n * 2 {
-            return None;
-        }
-        Some(i[0..2 * n].chunks(2).fold(Vec::new(), |mut acc, x| {
-            acc.push(self.u16(x).unwrap());
-            acc
-        }))
+        values(i, n, 2)?
+            .chunks_exact(2)
+            .map(|x| self.u16(x))
+            .collect()
     }
 
     pub(crate) fn u32(&self, i: &[u8]) -> Option<u32> {
@@ -308,14 +401,10 @@ impl Bytes {
     }
 
     fn u32_vec(&self, n: u64, i: &[u8]) -> Option<Vec<u32>> {
-        let n = n as usize;
-        if i.len() < n * 4 {
-            return None;
-        }
-        Some(i[0..4 * n].chunks(4).fold(Vec::new(), |mut acc, x| {
-            acc.push(self.u32(x).unwrap());
-            acc
-        }))
+        values(i, n, 4)?
+            .chunks_exact(4)
+            .map(|x| self.u32(x))
+            .collect()
     }
 
     pub(crate) fn u64(&self, i: &[u8]) -> Option<u64> {
@@ -347,13 +436,107 @@ impl Bytes {
     }
 
     fn u64_vec(&self, n: u64, i: &[u8]) -> Option<Vec<u64>> {
-        let n = n as usize;
-        if i.len() < n * 8 {
-            return None;
+        values(i, n, 8)?
+            .chunks_exact(8)
+            .map(|x| self.u64(x))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests_tiff_parser {
+    use super::*;
+
+    #[test]
+    fn test_header_classic_intel() {
+        let buf = [0x49, 0x49, 0x2A, 0x00, 0x09, 0x00, 0x00, 0x00];
+        let result = Parser::header(&buf);
+        match result {
+            Ok((parser, next_ifd)) => {
+                assert_eq!(parser, Classic(Intel));
+                assert_eq!(next_ifd, 9);
+            }
+            _ => {
+                panic!("Failed to parse header");
+            }
         }
-        Some(i[0..8 * n].chunks(8).fold(Vec::new(), |mut acc, x| {
-            acc.push(self.u64(x).unwrap());
-            acc
-        }))
+    }
+
+    #[test]
+    fn test_entry_every_data_type() {
+        for (p, be) in [(Classic(Intel), false), (Big(Moto), true)] {
+            macro_rules! b {
+                ($($x:expr),*) => {
+                    [$(if be { $x.to_be_bytes().to_vec() } else { $x.to_le_bytes().to_vec() }),*]
+                        .concat()
+                };
+            }
+            let cases = [
+                (SBYTE, 1, b!(-2i8), SByte(-2)),
+                (SBYTE, 3, b!(-1i8, 0i8, 1i8), SByteVec(vec![-1, 0, 1])),
+                (SSHORT, 1, b!(-300i16), SShort(-300)),
+                (SSHORT, 2, b!(-1i16, 2i16), SShortVec(vec![-1, 2])),
+                (SLONG, 2, b!(-70000i32, 5i32), SLongVec(vec![-70000, 5])),
+                (SLONG8, 1, b!(-5i64), SLong8(-5)),
+                (IFD, 1, b!(8u32), Ifd(8)),
+                (IFD, 2, b!(8u32, 300u32), IfdVec(vec![8, 300])),
+                (SLONG8, 2, b!(i64::MIN, 7i64), SLong8Vec(vec![i64::MIN, 7])),
+                (
+                    RATIONAL,
+                    2,
+                    b!(1u32, 2u32, 3u32, 4u32),
+                    RationalVec(vec![(1, 2), (3, 4)]),
+                ),
+                (
+                    SRATIONAL,
+                    1,
+                    b!(-1i32, 3i32),
+                    SRational {
+                        numer: -1,
+                        denom: 3,
+                    },
+                ),
+                (
+                    SRATIONAL,
+                    2,
+                    b!(-1i32, 3i32, 5i32, -7i32),
+                    SRationalVec(vec![(-1, 3), (5, -7)]),
+                ),
+                (FLOAT, 1, b!(0.25f32), Float(0.25)),
+                (FLOAT, 2, b!(1.5f32, -2.0f32), FloatVec(vec![1.5, -2.0])),
+                (DOUBLE, 1, b!(0.00025f64), Double(0.00025)),
+                (
+                    DOUBLE,
+                    2,
+                    b!(-0.5f64, 1e300f64),
+                    DoubleVec(vec![-0.5, 1e300]),
+                ),
+            ];
+            for (dt, c, buf, data) in cases {
+                assert_eq!(p.entry(c, dt, &buf).unwrap(), data);
+            }
+            assert!(p.entry(2, DOUBLE, &b!(1.0f64)).is_err());
+            assert!(p.entry(2, SRATIONAL, &b!(1i32, 2i32)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ndpi_ifd_body() {
+        // StripOffsets(273) LONG 1 and NdpiMcuStarts(65426) LONG 2
+        let buf = [
+            0x11, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, //
+            0x92, 0xff, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00,
+        ];
+        let ext = [
+            0x30, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // next IFD
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, // high words
+        ];
+        let (mut entries, mut unloaded) = (HashMap::new(), Vec::new());
+        let next_ifd = Classic(Intel)
+            .ndpi_ifd_body(&buf, &ext, &mut entries, &mut unloaded)
+            .unwrap();
+        assert_eq!(next_ifd, Some(0x1_0000_0030));
+        assert_eq!(entries.get(&273), Some(&Long8(0x1_0000_0010)));
+        assert_eq!(unloaded, vec![(65426, 2, LONG, 0x2_0000_0020, 8)]);
     }
 }
@@ -1,8 +1,20 @@
 use super::{
-    data::{Data, Tag, Tiff, IFD},
+    data::{Data, DataType::*, Deferred, IFD},
+    parser::Parser,
     tag::*,
-    ParseTiffError,
 };
+use crate::error::EozinError;
+use crate::source::{read_bytes, AsyncByteSource};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Deferred arrays up to this are read whole on first use and kept, longer ones
+/// are read a page at a time.
+const LOAD_WHOLE_LEN: u64 = 1 << 20;
+
+/// Values of a page of a deferred array too long to be read whole, neighbouring
+/// tiles share a page.
+const PAGE_LEN: u64 = 1 << 12;
 
 #[derive(Debug)]
 pub(crate) struct TiledIfd {
@@ -10,17 +22,84 @@ pub(crate) struct TiledIfd {
     pub height: u64,
     pub tile_width: u64,
     pub tile_height: u64,
-    pub offsets: Vec<u64>,
-    pub byte_counts: Vec<u64>,
+    pub offsets: U64Array,
+    pub byte_counts: U64Array,
+}
+
+/// TileOffsets or TileByteCounts, which may be left in the file until a tile is read.
+/// A deferred array is kept whole once read, or by pages when it's long.
+#[derive(Debug)]
+pub(crate) enum U64Array {
+    Loaded(Vec<u64>),
+    Deferred(Deferred, OnceLock<Vec<u64>>, Mutex<HashMap<u64, Vec<u64>>>),
 }
 
-pub(crate) fn tiled_ifd<'a>(ifd: &IFD) -> Option<TiledIfd> {
+impl U64Array {
+    fn new(d: &Data) -> Option<Self> {
+        match d {
+            Data::Deferred(at) if matches!(at.data_type, SHORT | LONG | LONG8) => Some(
+                U64Array::Deferred(at.clone(), OnceLock::new(), Mutex::new(HashMap::new())),
+            ),
+            d => u64vec(d).map(U64Array::Loaded),
+        }
+    }
+
+    /// `i`th value, `None` beyond the array.
+    pub(crate) async fn get<S: AsyncByteSource + ?Sized>(
+        &self,
+        source: &S,
+        i: usize,
+    ) -> Result<Option<u64>, EozinError> {
+        let (at, loaded, pages) = match self {
+            U64Array::Loaded(v) => return Ok(v.get(i).copied()),
+            U64Array::Deferred(_, loaded, _) if loaded.get().is_some() => {
+                return Ok(loaded.get().and_then(|v| v.get(i).copied()));
+            }
+            U64Array::Deferred(at, loaded, pages) => (at, loaded, pages),
+        };
+        if i as u64 >= at.count {
+            return Ok(None);
+        }
+        if at.len() > LOAD_WHOLE_LEN {
+            let (page, at_page) = (i as u64 / PAGE_LEN, (i as u64 % PAGE_LEN) as usize);
+            let lock = || pages.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(v) = lock().get(&page) {
+                return Ok(v.get(at_page).copied());
+            }
+            let count = PAGE_LEN.min(at.count - page * PAGE_LEN);
+            let start = (page * PAGE_LEN)
+                .checked_mul(at.data_type.size())
+                .and_then(|o| o.checked_add(at.offset))
+                .ok_or(EozinError::new("Invalid byte range"))?;
+            let end = start.saturating_add(count * at.data_type.size());
+            let v = values(at, count, &read_bytes(source, start, end).await?)?;
+            let x = v.get(at_page).copied();
+            lock().insert(page, v);
+            return Ok(x);
+        }
+        let buf = read_bytes(source, at.offset, at.offset.saturating_add(at.len())).await?;
+        let v = values(at, at.count, &buf)?;
+        let x = v.get(i).copied();
+        let _ = loaded.set(v);
+        Ok(x)
+    }
+}
+
+fn values(at: &Deferred, count: u64, buf: &[u8]) -> Result<Vec<u64>, EozinError> {
+    let d = Parser::Classic(at.bytes).entry(count, at.data_type.clone(), buf)?;
+    u64s(&d).ok_or(EozinError::new("Offsets must be SHORT, LONG or LONG8"))
+}
+
+pub(crate) fn tiled_ifd(ifd: &IFD) -> Option<TiledIfd> {
     let width = ifd.get(&ImageWidth).and_then(to_u64)?;
-    let height = ifd.get(&ImageWidth).and_then(to_u64)?;
+    let height = ifd.get(&ImageLength).and_then(to_u64)?;
     let tile_width = ifd.get(&TileWidth).and_then(to_u64)?;
     let tile_height = ifd.get(&TileLength).and_then(to_u64)?;
-    let offsets = ifd.get(&TileOffsets).and_then(u64vec)?;
-    let byte_counts = ifd.get(&TileByteCounts).and_then(u64vec)?;
+    let offsets = ifd.get(&TileOffsets).and_then(U64Array::new)?;
+    let byte_counts = ifd.get(&TileByteCounts).and_then(U64Array::new)?;
+    if tile_width == 0 || tile_height == 0 {
+        return None;
+    }
     Some(TiledIfd {
         width,
         height,
@@ -31,22 +110,69 @@ pub(crate) fn tiled_ifd<'a>(ifd: &IFD) -> Option<TiledIfd> {
     })
 }
 
-fn u8vec(d: &Data) -> Option<&Vec<u8>> {
-    match d {
-        Data::UndefinedVec(v) => Some(v),
-        Data::ByteVec(v) => Some(v),
-        _ => None,
+/// Image stored as rows of strips, every strip but the last has `rows_per_strip` rows.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct StripedIfd {
+    pub width: u64,
+    pub height: u64,
+    pub rows_per_strip: u64,
+    pub offsets: Vec<u64>,
+    pub byte_counts: Vec<u64>,
+}
+
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+impl StripedIfd {
+    /// Rows of the `i`th strip.
+    pub(crate) fn rows(&self, i: u64) -> u64 {
+        self.height
+            .saturating_sub(i.saturating_mul(self.rows_per_strip))
+            .min(self.rows_per_strip)
+    }
+}
+
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+pub(crate) fn striped_ifd(ifd: &IFD) -> Option<StripedIfd> {
+    let width = ifd.get(&ImageWidth).and_then(to_u64)?;
+    let height = ifd.get(&ImageLength).and_then(to_u64)?;
+    let rows_per_strip = ifd.get(&RowsPerStrip).and_then(to_u64).unwrap_or(height);
+    let offsets = ifd.get(&StripOffsets).and_then(u64s)?;
+    let byte_counts = ifd.get(&StripByteCounts).and_then(u64s)?;
+    if rows_per_strip == 0 || offsets.len() != byte_counts.len() {
+        return None;
+    }
+    Some(StripedIfd {
+        width,
+        height,
+        rows_per_strip: rows_per_strip.min(height),
+        offsets,
+        byte_counts,
+    })
+}
+
+/// Directories nested under a pointer tag such as SubIFDs, none if it isn't read.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+pub(crate) fn sub_ifds(ifd: &IFD, tag: u16) -> &[IFD] {
+    match ifd.get(&tag) {
+        Some(Data::Ifds(v)) => v,
+        _ => &[],
     }
 }
 
-fn u64vec(d: &Data) -> Option<Vec<u64>> {
+/// Array or single value, e.g. StripOffsets of a single strip image.
+pub(crate) fn u64s(d: &Data) -> Option<Vec<u64>> {
+    u64vec(d).or_else(|| to_u64(d).map(|x| vec![x]))
+}
+
+pub(crate) fn u64vec(d: &Data) -> Option<Vec<u64>> {
     match d {
+        Data::ShortVec(v) => Some(v.iter().map(|&x| x as u64).collect()),
         Data::LongVec(v) => Some(v.iter().map(|&x| x as u64).collect()),
         Data::Long8Vec(v) => Some(v.clone()),
         _ => None,
     }
 }
-fn to_u64(d: &Data) -> Option<u64> {
+pub(crate) fn to_u64(d: &Data) -> Option<u64> {
     match d {
         Data::Undefined(b) => Some(*b as u64),
         Data::Byte(b) => Some(*b as u64),
@@ -56,3 +182,119 @@ fn to_u64(d: &Data) -> Option<u64> {
         _ => None,
     }
 }
+
+pub(crate) fn u8vec(d: &Data) -> Option<&Vec<u8>> {
+    match d {
+        Data::UndefinedVec(v) => Some(v),
+        Data::ByteVec(v) => Some(v),
+        _ => None,
+    }
+}
+
+pub(crate) fn first_short(d: &Data) -> Option<u16> {
+    match d {
+        Data::ShortVec(v) => v.first().copied(),
+        d => expect_short(d),
+    }
+}
+
+pub(crate) fn expect_short(d: &Data) -> Option<u16> {
+    match d {
+        Data::Short(b) => Some(*b),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests_property {
+    use super::*;
+    use crate::source::{block_on, ByteSource, SlideSource};
+    use crate::tiff::parser::Bytes;
+
+    /// Async only source, vendors read it through a [`SlideSource`].
+    struct Remote(Vec<u8>);
+
+    impl AsyncByteSource for Remote {
+        async fn read_at(&self, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+            ByteSource::read_at(&self.0, offset, len)
+        }
+
+        async fn size(&self) -> std::io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    fn deferred(count: u32) -> (U64Array, Vec<u8>) {
+        let mut buf = vec![0; 8];
+        buf.extend((0..count).flat_map(|i| (i * 10).to_be_bytes()));
+        let at = Deferred {
+            bytes: Bytes::Moto,
+            data_type: LONG,
+            count: count as u64,
+            offset: 8,
+        };
+        (
+            U64Array::Deferred(at, OnceLock::new(), Mutex::new(HashMap::new())),
+            buf,
+        )
+    }
+
+    fn is_loaded(a: &U64Array) -> bool {
+        matches!(a, U64Array::Deferred(_, loaded, _) if loaded.get().is_some())
+    }
+
+    #[test]
+    fn test_deferred_array() {
+        let (a, buf) = deferred(2000);
+        assert_eq!(block_on(a.get(&buf, 1234)).unwrap(), Some(12340));
+        assert!(is_loaded(&a));
+        assert_eq!(block_on(a.get(&buf, 2000)).unwrap(), None);
+
+        let (a, buf) = deferred(300_000);
+        assert_eq!(block_on(a.get(&buf, 299_999)).unwrap(), Some(2_999_990));
+        assert!(!is_loaded(&a));
+        // the page of the last value is kept, the rest of the array isn't read
+        assert_eq!(block_on(a.get(&vec![], 299_100)).unwrap(), Some(2_991_000));
+        assert!(block_on(a.get(&vec![], 0)).is_err());
+
+        let (a, buf) = deferred(2000);
+        let remote = Remote(buf);
+        let s = SlideSource::new(None, 8 + 8000);
+        let x = block_on(s.run(&remote, Box::pin(a.get(&s, 7))));
+        assert_eq!(x.unwrap(), Some(70));
+        assert!(is_loaded(&a));
+        assert_eq!(block_on(a.get(&remote, 1999)).unwrap(), Some(19990));
+
+        let at = Deferred {
+            bytes: Bytes::Moto,
+            data_type: LONG8,
+            count: u64::MAX / 4,
+            offset: u64::MAX - 4,
+        };
+        let a = U64Array::Deferred(at, OnceLock::new(), Mutex::new(HashMap::new()));
+        assert!(block_on(a.get(&vec![0; 16], 1)).is_err());
+    }
+
+    #[test]
+    fn test_striped_ifd() {
+        let ifd = IFD::from([
+            (ImageWidth, Data::Short(100)),
+            (ImageLength, Data::Short(40)),
+            (RowsPerStrip, Data::Short(16)),
+            (StripOffsets, Data::LongVec(vec![8, 108, 208])),
+            (StripByteCounts, Data::ShortVec(vec![100, 100, 50])),
+        ]);
+        let t = striped_ifd(&ifd).unwrap();
+        assert_eq!(t.byte_counts, vec![100, 100, 50]);
+        assert_eq!((t.rows(0), t.rows(2), t.rows(3)), (16, 8, 0));
+        assert_eq!(t.rows(u64::MAX), 0);
+
+        let single = IFD::from([
+            (ImageWidth, Data::Short(100)),
+            (ImageLength, Data::Short(40)),
+            (StripOffsets, Data::Long(8)),
+            (StripByteCounts, Data::Long(4000)),
+        ]);
+        assert_eq!(striped_ifd(&single).unwrap().rows_per_strip, 40);
+    }
+}
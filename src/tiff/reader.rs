@@ -0,0 +1,354 @@
+use super::{
+    data::{Data, Deferred, Tag},
+    property,
+    tag::{
+        ExifIFD, InteroperabilityIFD, NdpiFormatFlag, SubIFDs, TileByteCounts, TileOffsets, GPSIFD,
+    },
+    ParseTiffError, Parser, Tiff, IFD,
+};
+use crate::error::EozinError;
+use crate::source::{read_bytes, AsyncByteSource};
+use std::collections::{HashMap, HashSet};
+
+/// TileOffsets and TileByteCounts longer than this are left in the file until a tile
+/// of their level is read, so opening reads a few small ranges.
+const DEFER_LEN: u64 = 4096;
+
+/// Tags whose LONG or LONG8 values are offsets of IFDs, entries of the IFD and IFD8
+/// types are followed whatever their tag is.
+const IFD_POINTERS: [Tag; 4] = [SubIFDs, ExifIFD, GPSIFD, InteroperabilityIFD];
+
+/// Nesting followed below the main chain, EXIF in a SubIFD with its Interoperability
+/// IFD is three levels deep.
+const MAX_DEPTH: usize = 4;
+
+/// Walks every IFD of `source`, NDPI beyond 4GB keeps the high bits of its offsets
+/// after the IFD entries. Every range is checked against the file before it's read,
+/// so broken offsets fail without allocating and a looping IFD chain is refused.
+///
+/// Directories under pointer tags are read after the main chain and nested into the
+/// entry pointing to them, a broken one leaves the entry as its offsets.
+pub(crate) async fn read_tiff<S: AsyncByteSource + ?Sized>(source: &S) -> Result<Tiff, EozinError> {
+    let file_size = source.size().await?;
+    let buf = read_bytes(source, 0, file_size.min(16)).await?;
+    let (p, ifd_offset) = Parser::header(&buf)?;
+    let mut w = Walker {
+        source,
+        p,
+        file_size,
+        beyond_4gb: matches!(p, Parser::Classic(_)) && file_size > u32::MAX as u64,
+        is_ndpi: None,
+        visited: HashSet::new(),
+        nodes: Vec::new(),
+    };
+    let mut top = Vec::new();
+    let mut next_ifd = Some(ifd_offset);
+    while let Some(ofs) = next_ifd {
+        if !w.visited.insert(ofs) {
+            return Err(ParseTiffError::IfdLoop(ofs).into());
+        }
+        top.push(w.nodes.len());
+        next_ifd = w.node(ofs, 0).await?;
+    }
+    // breadth first, so children always come after their parent in `nodes`
+    let mut i = 0;
+    while i < w.nodes.len() {
+        let depth = w.nodes[i].depth;
+        if depth < MAX_DEPTH {
+            for (tag, offsets) in pointers(&w.nodes[i].ifd) {
+                let n = w.nodes.len();
+                match w.chains(&offsets, depth + 1).await {
+                    Ok(children) if !children.is_empty() => {
+                        w.nodes[i].children.push((tag, children))
+                    }
+                    Ok(_) => {}
+                    Err(_) => w.nodes.truncate(n),
+                }
+            }
+        }
+        i += 1;
+    }
+    let mut nodes = w.nodes;
+    for i in (0..nodes.len()).rev() {
+        for (tag, children) in std::mem::take(&mut nodes[i].children) {
+            let ifds = children
+                .into_iter()
+                .map(|j| std::mem::take(&mut nodes[j].ifd))
+                .collect();
+            nodes[i].ifd.insert(tag, Data::Ifds(ifds));
+        }
+    }
+    Ok(top
+        .into_iter()
+        .map(|j| std::mem::take(&mut nodes[j].ifd))
+        .collect())
+}
+
+/// IFD read by the walk, `children` are indices of the directories nested under a tag.
+struct Node {
+    ifd: IFD,
+    depth: usize,
+    children: Vec<(Tag, Vec<usize>)>,
+}
+
+struct Walker<'a, S: ?Sized> {
+    source: &'a S,
+    p: Parser,
+    file_size: u64,
+    beyond_4gb: bool,
+    /// Decided by the first IFD.
+    is_ndpi: Option<bool>,
+    visited: HashSet<u64>,
+    nodes: Vec<Node>,
+}
+
+impl<S: AsyncByteSource + ?Sized> Walker<'_, S> {
+    fn range(&self, start: u64, len: u64) -> Result<(u64, u64), ParseTiffError> {
+        start
+            .checked_add(len)
+            .filter(|end| *end <= self.file_size)
+            .map(|end| (start, end))
+            .ok_or(ParseTiffError::OffsetOutOfFile(start))
+    }
+
+    /// Reads the IFD at `ofs` into a new node and returns the next IFD offset.
+    async fn node(&mut self, ofs: u64, depth: usize) -> Result<Option<u64>, EozinError> {
+        let (p, source) = (self.p, self.source);
+        let size = p.size();
+        let mut entries = HashMap::new();
+        let mut unloaded = Vec::new();
+        let (start, end) = self.range(ofs, size.ifd_header)?;
+        let count = p.ifd_count(&read_bytes(source, start, end).await?)?;
+        let (start, end) = self.range(end, size.ifd_body(count))?;
+        let buf = read_bytes(source, start, end).await?;
+        let mut next_ifd = p.ifd_body(&buf, &mut entries, &mut unloaded)?;
+        let is_ndpi = *self
+            .is_ndpi
+            .get_or_insert_with(|| entries.contains_key(&NdpiFormatFlag));
+        if self.beyond_4gb && is_ndpi {
+            let (start, end) = self.range(start + 12 * count, 8 + 4 * count)?;
+            let ext = read_bytes(source, start, end).await?;
+            entries.clear();
+            unloaded.clear();
+            next_ifd = p.ndpi_ifd_body(
+                &buf[..12 * count as usize],
+                &ext,
+                &mut entries,
+                &mut unloaded,
+            )?;
+        }
+        for (tag, c, dt, addr, len) in unloaded.into_iter() {
+            let (start, end) = self.range(addr, len)?;
+            if len > DEFER_LEN && (tag == TileOffsets || tag == TileByteCounts) {
+                let deferred = Deferred {
+                    bytes: p.bytes(),
+                    data_type: dt,
+                    count: c,
+                    offset: start,
+                };
+                entries.insert(tag, Data::Deferred(deferred));
+                continue;
+            }
+            let buf = read_bytes(source, start, end).await?;
+            let data = p.entry(c, dt, &buf)?;
+            entries.insert(tag, data);
+        }
+        self.nodes.push(Node {
+            ifd: entries,
+            depth,
+            children: Vec::new(),
+        });
+        Ok(next_ifd)
+    }
+
+    /// Reads the IFDs at `offsets` with the IFDs chained after each, directories
+    /// already read are skipped. Returns their node indices.
+    async fn chains(&mut self, offsets: &[u64], depth: usize) -> Result<Vec<usize>, EozinError> {
+        let mut children = Vec::new();
+        for &ofs in offsets {
+            let mut next_ifd = Some(ofs).filter(|o| *o != 0);
+            while let Some(ofs) = next_ifd {
+                if !self.visited.insert(ofs) {
+                    break;
+                }
+                children.push(self.nodes.len());
+                next_ifd = self.node(ofs, depth).await?;
+            }
+        }
+        Ok(children)
+    }
+}
+
+/// Offsets of the directories each entry of `ifd` points to, in the order of tags.
+fn pointers(ifd: &IFD) -> Vec<(Tag, Vec<u64>)> {
+    let mut pointers: Vec<(Tag, Vec<u64>)> = ifd
+        .iter()
+        .filter_map(|(tag, d)| {
+            let offsets = match d {
+                Data::Ifd(x) => vec![*x as u64],
+                Data::IfdVec(v) => v.iter().map(|&x| x as u64).collect(),
+                Data::Ifd8(x) => vec![*x],
+                Data::Ifd8Vec(v) => v.clone(),
+                d if IFD_POINTERS.contains(tag) => property::u64s(d)?,
+                _ => return None,
+            };
+            Some((*tag, offsets))
+        })
+        .collect();
+    pointers.sort_by_key(|(tag, _)| *tag);
+    pointers
+}
+
+#[cfg(test)]
+mod tests_reader {
+    use super::*;
+    use crate::source::{block_on, ByteSource};
+
+    /// Little endian IFD, `entries` are (tag, type, count, value).
+    fn ifd(entries: &[(u16, u16, u32, u32)], next_ifd: u32) -> Vec<u8> {
+        let mut buf = (entries.len() as u16).to_le_bytes().to_vec();
+        for (tag, dt, count, v) in entries {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(dt.to_le_bytes());
+            buf.extend(count.to_le_bytes());
+            buf.extend(v.to_le_bytes());
+        }
+        buf.extend(next_ifd.to_le_bytes());
+        buf
+    }
+
+    /// Classic little endian tiff of one IFD at 8.
+    fn tiff(entries: &[(u16, u16, u32, u32)], next_ifd: u32) -> Vec<u8> {
+        [b"II*\0\x08\0\0\0".to_vec(), ifd(entries, next_ifd)].concat()
+    }
+
+    fn error(buf: &[u8]) -> String {
+        match block_on(read_tiff(&buf.to_vec())) {
+            Ok(_) => panic!("broken tiff is read"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_read_tiff() {
+        let buf = tiff(&[(256, 3, 1, 2), (273, 4, 2, 8)], 0);
+        let t = block_on(read_tiff(&buf)).unwrap();
+        assert_eq!(t.len(), 1);
+        assert_eq!(t[0].get(&256), Some(&crate::tiff::Data::Short(2)));
+        for n in 0..buf.len() {
+            assert!(block_on(read_tiff(&buf[..n].to_vec())).is_err());
+        }
+    }
+
+    #[test]
+    fn test_defer_tile_offsets() {
+        let mut buf = tiff(&[(256, 3, 1, 2), (324, 4, 1100, 50), (325, 4, 2, 50)], 0);
+        buf.resize(50 + 4400, 0);
+        let t = block_on(read_tiff(&buf)).unwrap();
+        match t[0].get(&324) {
+            Some(Data::Deferred(d)) => assert_eq!((d.count(), d.offset()), (1100, 50)),
+            d => panic!("TileOffsets are read as {:?}", d),
+        }
+        assert_eq!(t[0].get(&325), Some(&Data::LongVec(vec![0, 0])));
+        buf.truncate(50 + 4399);
+        assert!(error(&buf).contains("out of the file"));
+    }
+
+    #[test]
+    fn test_nested_ifds() {
+        let width = |w| HashMap::from([(256, Data::Short(w))]);
+        let buf = [
+            // SubIFDs at 70 and 106, 88 is chained after 70, GPS points out of the file
+            tiff(
+                &[
+                    (256, 3, 1, 4),
+                    (330, 4, 2, 62),
+                    (34665, 13, 1, 124),
+                    (34853, 4, 1, 5000),
+                ],
+                0,
+            ),
+            [70u32, 106].iter().flat_map(|o| o.to_le_bytes()).collect(),
+            ifd(&[(256, 3, 1, 3)], 88),
+            ifd(&[(256, 3, 1, 2)], 0),
+            ifd(&[(256, 3, 1, 1)], 0),
+            // Interoperability pointing at a SubIFD already read
+            ifd(&[(40965, 4, 1, 70)], 0),
+        ]
+        .concat();
+        let t = block_on(read_tiff(&buf)).unwrap();
+        assert_eq!(t.len(), 1);
+        assert_eq!(
+            t[0].get(&330),
+            Some(&Data::Ifds(vec![width(3), width(2), width(1)]))
+        );
+        let interop = HashMap::from([(40965, Data::Long(70))]);
+        assert_eq!(t[0].get(&34665), Some(&Data::Ifds(vec![interop])));
+        assert_eq!(t[0].get(&34853), Some(&Data::Long(5000)));
+    }
+
+    /// Zeros but `chunks` up to `size`, so files beyond 4GB are read without allocating them.
+    struct Sparse {
+        size: u64,
+        chunks: Vec<(u64, Vec<u8>)>,
+    }
+
+    impl ByteSource for Sparse {
+        fn read_at(&self, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+            if offset + len as u64 > self.size {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut buf = vec![0; len];
+            for (start, chunk) in self.chunks.iter() {
+                for (i, b) in chunk.iter().enumerate() {
+                    if let Some(j) = (start + i as u64).checked_sub(offset) {
+                        if let Some(x) = buf.get_mut(j as usize) {
+                            *x = *b;
+                        }
+                    }
+                }
+            }
+            Ok(buf)
+        }
+
+        fn size(&self) -> std::io::Result<u64> {
+            Ok(self.size)
+        }
+    }
+
+    #[test]
+    fn test_ndpi_beyond_4gb() {
+        let entries = [(273, 4, 1, 0x10), (65420, 4, 1, 1), (65426, 4, 2, 0x20)];
+        // 8 bytes next IFD offset and the high words of StripOffsets and McuStarts
+        let mut head = tiff(&entries, 0);
+        head.extend([0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        let mcu_starts = [5u32, 6].iter().flat_map(|x| x.to_le_bytes()).collect();
+        let source = Sparse {
+            size: 5 << 30,
+            chunks: vec![(0, head), ((1 << 32) + 0x20, mcu_starts)],
+        };
+        let t = block_on(read_tiff(&source)).unwrap();
+        assert_eq!(t.len(), 1);
+        assert_eq!(t[0].get(&273), Some(&Data::Long8((1 << 32) + 0x10)));
+        assert_eq!(t[0].get(&65420), Some(&Data::Long(1)));
+        assert_eq!(t[0].get(&65426), Some(&Data::LongVec(vec![5, 6])));
+        // the same IFD within 4GB is a classic one
+        let near = Sparse {
+            size: 1 << 20,
+            ..source
+        };
+        let t = block_on(read_tiff(&near)).unwrap();
+        assert_eq!(t[0].get(&273), Some(&Data::Long(0x10)));
+    }
+
+    #[test]
+    fn test_read_broken_tiff() {
+        assert!(error(&tiff(&[(256, 3, 1, 2)], 8)).contains("loop"));
+        assert!(error(&tiff(&[(273, 4, 2, 1000)], 0)).contains("out of the file"));
+        assert!(error(&tiff(&[(273, 4, u32::MAX, 8)], 0)).contains("too many"));
+        let mut big = b"II+\0\x08\0\0\0\x10\0\0\0\0\0\0\0".to_vec();
+        big.extend(u64::MAX.to_le_bytes());
+        assert!(error(&big).contains("too many"));
+        assert!(error(b"II*\0\xff\xff\xff\xff").contains("out of the file"));
+    }
+}
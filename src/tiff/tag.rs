@@ -1,21 +1,47 @@
 #![allow(non_upper_case_globals)]
 #![allow(dead_code)]
-pub(crate) const ImageWidth: u16 = 256;
-pub(crate) const ImageLength: u16 = 257;
-pub(crate) const BitPerSample: u16 = 258;
-pub(crate) const Compression: u16 = 259;
-pub(crate) const PhotometricInterpretation: u16 = 262;
-pub(crate) const ImageDescription: u16 = 270;
-pub(crate) const StripOffsets: u16 = 273;
+pub const NewSubfileType: u16 = 254;
+pub const ImageWidth: u16 = 256;
+pub const ImageLength: u16 = 257;
+pub const BitPerSample: u16 = 258;
+pub const Compression: u16 = 259;
+pub const PhotometricInterpretation: u16 = 262;
+pub const DocumentName: u16 = 269;
+pub const ImageDescription: u16 = 270;
+pub const Make: u16 = 271;
+pub const Model: u16 = 272;
+pub const StripOffsets: u16 = 273;
 
-pub(crate) const Orientation: u16 = 274;
-pub(crate) const SamplesPerPixel: u16 = 277;
-const RowsPerStrip: u16 = 278;
-const StripByteCounts: u16 = 279;
-const PlanarConfiguration: u16 = 284;
-const Predictor: u16 = 317;
-pub(crate) const TileWidth: u16 = 322;
-pub(crate) const TileLength: u16 = 323;
-pub(crate) const TileOffsets: u16 = 324;
-pub(crate) const TileByteCounts: u16 = 325;
-pub(crate) const JPEGTables: u16 = 347;
+pub const Orientation: u16 = 274;
+pub const SamplesPerPixel: u16 = 277;
+pub const RowsPerStrip: u16 = 278;
+pub const StripByteCounts: u16 = 279;
+pub const XResolution: u16 = 282;
+pub const YResolution: u16 = 283;
+pub const PlanarConfiguration: u16 = 284;
+pub const ResolutionUnit: u16 = 296;
+pub const Software: u16 = 305;
+pub const DateTime: u16 = 306;
+pub const Artist: u16 = 315;
+pub const HostComputer: u16 = 316;
+pub const Predictor: u16 = 317;
+pub const TileWidth: u16 = 322;
+pub const TileLength: u16 = 323;
+pub const TileOffsets: u16 = 324;
+pub const TileByteCounts: u16 = 325;
+pub const SubIFDs: u16 = 330;
+pub const JPEGTables: u16 = 347;
+pub const XMLPacket: u16 = 700;
+pub const Copyright: u16 = 33432;
+pub const ExifIFD: u16 = 34665;
+pub const GPSIFD: u16 = 34853;
+pub const InteroperabilityIFD: u16 = 40965;
+
+// Hamamatsu NDPI private tags
+pub const NdpiFormatFlag: u16 = 65420;
+pub const NdpiSourceLens: u16 = 65421;
+pub const NdpiXOffsetFromSlideCentre: u16 = 65422;
+pub const NdpiYOffsetFromSlideCentre: u16 = 65423;
+pub const NdpiFocalPlane: u16 = 65424;
+pub const NdpiMcuStarts: u16 = 65426;
+pub const NdpiMcuStartsHighBytes: u16 = 65432;
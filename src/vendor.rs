@@ -1,3 +1,7 @@
 pub(crate) mod aperio;
+pub(crate) mod generic;
 pub(crate) mod leica;
-
+pub(crate) mod ndpi;
+pub(crate) mod openslide;
+pub(crate) mod philips;
+pub(crate) mod ventana;
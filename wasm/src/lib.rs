@@ -1,10 +1,10 @@
 use wasm_bindgen::prelude::*;
 
-use eozin;
+use eozin::wasm::WebSource;
 
 #[wasm_bindgen]
 pub struct Eozin {
-    aperio: eozin::wasm::Aperio,
+    slide: eozin::wasm::Eozin<WebSource>,
     pub level_count: u64,
 }
 
@@ -12,22 +12,87 @@ pub struct Eozin {
 impl Eozin {
     #[wasm_bindgen(constructor)]
     pub async fn new(blob: web_sys::Blob) -> Result<Eozin, JsError> {
-        let aperio = eozin::wasm::Aperio::open(blob).await?;
-        let level_count = aperio.level_count.clone();
-        Ok(Eozin {
-            aperio,
-            level_count,
-        })
+        let slide = eozin::wasm::Eozin::open_blob(blob).await?;
+        let level_count = slide.level_count;
+        Ok(Eozin { slide, level_count })
+    }
+
+    /// Slide behind a server answering `Range` requests, which exposes `Content-Range`.
+    pub async fn from_url(url: String) -> Result<Eozin, JsError> {
+        let slide = eozin::wasm::Eozin::open_fetch(&url).await?;
+        let level_count = slide.level_count;
+        Ok(Eozin { slide, level_count })
+    }
+
+    #[wasm_bindgen(method)]
+    pub async fn read_tile(&self, lv: usize, x: usize, y: usize) -> Result<web_sys::Blob, JsError> {
+        self.slide
+            .read_tile_blob(lv, x, y)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Vendor which opened the slide, e.g. `aperio` or `hamamatsu`.
+    #[wasm_bindgen(getter)]
+    pub fn vendor(&self) -> String {
+        self.slide.vendor.clone()
     }
 
     #[wasm_bindgen(method)]
-    pub async fn read_tile(
-        &mut self,
+    pub fn get_best_level_for_downsample(&self, downsample: f64) -> usize {
+        self.slide.get_best_level_for_downsample(downsample)
+    }
+
+    /// RGBA pixels at (x, y) of level 0 read from `lv`, transparent outside the slide.
+    #[wasm_bindgen(method)]
+    pub async fn read_region(
+        &self,
+        x: i32,
+        y: i32,
         lv: usize,
-        x: usize,
-        y: usize,
-    ) -> Result<web_sys::Blob, JsError> {
-        self.aperio.read_tile(lv, x, y).await.map_err(|e| e.into())
+        width: u32,
+        height: u32,
+    ) -> Result<web_sys::ImageData, JsError> {
+        let img = self
+            .slide
+            .read_region_async((x as i64, y as i64), lv, (width as u64, height as u64))
+            .await?;
+        web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+            wasm_bindgen::Clamped(&img.data),
+            width,
+            height,
+        )
+        .map_err(|_| JsError::new("failed to create ImageData"))
+    }
+
+    /// RGBA thumbnail fitting in width x height.
+    #[wasm_bindgen(method)]
+    pub async fn get_thumbnail(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<web_sys::ImageData, JsError> {
+        let img = self
+            .slide
+            .get_thumbnail_async((width as u64, height as u64))
+            .await?;
+        web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+            wasm_bindgen::Clamped(&img.data),
+            img.width as u32,
+            img.height as u32,
+        )
+        .map_err(|_| JsError::new("failed to create ImageData"))
+    }
+
+    /// OpenSlide-compatible properties as a plain object.
+    #[wasm_bindgen(getter)]
+    pub fn properties(&self) -> Result<js_sys::Object, JsError> {
+        let obj = js_sys::Object::new();
+        for (k, v) in self.slide.properties().iter() {
+            js_sys::Reflect::set(&obj, &k.into(), &v.into())
+                .map_err(|_| JsError::new("failed to set property"))?;
+        }
+        Ok(obj)
     }
 }
 #[wasm_bindgen]